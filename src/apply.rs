@@ -0,0 +1,46 @@
+use cargo::CargoConfig;
+use lockfile::Lockfile;
+use std::io;
+
+/// Instead of a blanket `cargo update`, updates only the locked packages that have just been yanked,
+/// and prints what changed in `Cargo.lock`. `changed` is from `set_yanked_state`.
+pub fn update_yanked_locked_packages(cargo: &CargoConfig, changed: &[(String, String, bool)]) -> io::Result<()> {
+    let lockfile_path = cargo.lockfile_path();
+    if !lockfile_path.exists() {
+        println!("There's no Cargo.lock to update. Run `cargo generate-lockfile`");
+        return Ok(());
+    }
+    let before = Lockfile::load(&lockfile_path)?;
+
+    let mut pkgids = Vec::new();
+    for pkg in before.crates_io_packages() {
+        let just_yanked = changed.iter().any(|c| {
+            c.2 && c.0.to_lowercase() == pkg.name.to_lowercase() && c.1 == pkg.version
+        });
+        if just_yanked {
+            pkgids.push(format!("{}@{}", pkg.name, pkg.version));
+        }
+    }
+    if pkgids.is_empty() {
+        println!("No packages in Cargo.lock have just been yanked");
+        return Ok(());
+    }
+
+    println!("Updating {}", pkgids.join(", "));
+    cargo.cargo_update_packages(&pkgids)?;
+
+    let after = Lockfile::load(&lockfile_path)?;
+    let (removed, added) = before.difference(&after);
+    if removed.is_empty() && added.is_empty() {
+        println!("Cargo.lock hasn't changed");
+        return Ok(());
+    }
+    println!("Cargo.lock changes:");
+    for pkg in removed {
+        println!("  - {} {}", pkg.name, pkg.version);
+    }
+    for pkg in added {
+        println!("  + {} {}", pkg.name, pkg.version);
+    }
+    Ok(())
+}
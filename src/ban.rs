@@ -0,0 +1,155 @@
+use regfork::{ForkedRegistryIndex, YankSpec};
+use summary::IndexSummary;
+use semver::Version as SemVer;
+use semver::VersionReq;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+
+/// Yanks versions matching the specs. With `propagate` it also yanks every version that has a required dependency
+/// which can only be satisfied by banned versions, and repeats that until nothing more needs to be yanked.
+///
+/// Dependencies that were already unsatisfiable before the ban are left alone.
+pub fn ban(fork: &ForkedRegistryIndex, specs: &[YankSpec], propagate: bool) -> io::Result<()> {
+    let _lock = fork.lock()?;
+    fork.apply_edits(|edits| {
+        // lowercase crate name -> versions banned directly or by propagation
+        let mut banned: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut any_modified = false;
+        let mut changed_crates = Vec::new();
+
+        for spec in specs {
+            let name = spec.crate_name.to_lowercase();
+            let versions = banned.entry(name.clone()).or_insert_with(HashSet::new);
+            let already_banned = versions.len();
+            any_modified |= fork.rewrite_crate_file(edits, &spec.crate_name, false, |ver| {
+                if spec.matches_version(&ver.vers) {
+                    if !ver.yanked {
+                        versions.insert(ver.vers.clone());
+                    }
+                    Some(true)
+                } else {
+                    None
+                }
+            })?;
+            println!("Banned {} versions of {}", versions.len() - already_banned, spec.crate_name);
+            changed_crates.push(name);
+        }
+
+        if !propagate {
+            return Ok(any_modified);
+        }
+        // it may not have the uncommitted bans, but they're in `banned`
+        let summary = fork.index_summary()?;
+        for (round, to_yank) in propagate_bans(&summary, &mut banned, changed_crates).into_iter().enumerate() {
+            println!("Round {}: yanking versions that depend only on banned versions", round + 1);
+            for (crate_name, versions) in to_yank {
+                for v in &versions {
+                    println!("    {} {} requires {}", crate_name, v.0, v.1);
+                }
+                any_modified |= fork.rewrite_crate_file(edits, &crate_name, false, |ver| {
+                    if versions.iter().any(|v| v.0 == ver.vers) { Some(true) } else { None }
+                })?;
+            }
+        }
+
+        Ok(any_modified)
+    })
+}
+
+/// Versions to yank in each round of propagation, until nothing more depends only on banned versions.
+/// Adds them to `banned`.
+fn propagate_bans(summary: &IndexSummary, banned: &mut HashMap<String, HashSet<String>>, mut changed_crates: Vec<String>) -> Vec<BTreeMap<String, Vec<(String, String)>>> {
+    let mut rounds = Vec::new();
+    while !changed_crates.is_empty() {
+        let to_yank = unsatisfiable_dependents(summary, &changed_crates, banned);
+        if to_yank.is_empty() {
+            break;
+        }
+        changed_crates.clear();
+        for (crate_name, versions) in &to_yank {
+            let name = crate_name.to_lowercase();
+            banned.entry(name.clone()).or_insert_with(HashSet::new).extend(versions.iter().map(|v| v.0.clone()));
+            changed_crates.push(name);
+        }
+        rounds.push(to_yank);
+    }
+    rounds
+}
+
+/// Unyanked versions of any crate with a required dependency on `changed_crates`
+/// that only banned or yanked versions can satisfy. Returns versions with the requirement that broke.
+fn unsatisfiable_dependents(summary: &IndexSummary, changed_crates: &[String], banned: &HashMap<String, HashSet<String>>) -> BTreeMap<String, Vec<(String, String)>> {
+    let mut to_yank = BTreeMap::new();
+    for c in summary.crates.values() {
+        let banned_here = banned.get(&c.name.to_lowercase());
+        for ver in &c.versions {
+            if ver.yanked || banned_here.map_or(false, |b| b.contains(&ver.vers)) {
+                continue;
+            }
+            for dep in &ver.deps {
+                if !dep.is_required() {
+                    continue;
+                }
+                let dep_name = dep.name.to_lowercase();
+                if !changed_crates.contains(&dep_name) {
+                    continue;
+                }
+                let target = match summary.crates.get(&dep_name) {
+                    Some(t) => t,
+                    None => continue,
+                };
+                let req = match VersionReq::parse(&dep.req) {
+                    Ok(req) => req,
+                    Err(_) => continue,
+                };
+                let banned_there = banned.get(&dep_name);
+                let mut any_banned = false;
+                let mut any_available = false;
+                for t in &target.versions {
+                    if !SemVer::parse(&t.vers).map(|v| req.matches(&v)).unwrap_or(false) {
+                        continue;
+                    }
+                    if banned_there.map_or(false, |b| b.contains(&t.vers)) {
+                        any_banned = true;
+                    } else if !t.yanked {
+                        any_available = true;
+                        break;
+                    }
+                }
+                if any_banned && !any_available {
+                    to_yank.entry(c.name.clone()).or_insert_with(Vec::new)
+                        .push((ver.vers.clone(), format!("{} {}", dep.name, dep.req)));
+                    break;
+                }
+            }
+        }
+    }
+    to_yank
+}
+
+#[test]
+fn propagates_bans_until_nothing_changes() {
+    let summary = IndexSummary::deserialize(b"lts-index-summary 1 -
+a
+\t1.0.0\tn\t
+\t1.1.0\tn\t
+\t0.9.0\ty\t
+b
+\t1.0.0\tn\t\ta==1.1.0
+\t1.0.1\tn\t\ta=^1.0
+C
+\t2.0.0\tn\t\tB==1.0.0
+\t2.0.1\tn\t\tb=^1\t?a==1.1.0
+d
+\t1.0.0\tn\t\ta==0.9.0
+").unwrap();
+    let mut banned = HashMap::new();
+    banned.insert("a".to_string(), ["1.1.0".to_string()].iter().cloned().collect());
+    let rounds = propagate_bans(&summary, &mut banned, vec!["a".to_string()]);
+    assert_eq!(2, rounds.len());
+    assert_eq!(vec![("1.0.0".to_string(), "a =1.1.0".to_string())], rounds[0]["b"]);
+    assert_eq!(vec![("2.0.0".to_string(), "B =1.0.0".to_string())], rounds[1]["C"]);
+    // already unsatisfiable before the ban
+    assert!(!rounds.iter().any(|r| r.contains_key("d")));
+    assert!(banned["c"].contains("2.0.0") && !banned["c"].contains("2.0.1"));
+}
@@ -1,5 +1,6 @@
 use write;
 use io_err;
+use unique_temp_dir;
 use std::process::Command;
 use std::env;
 use std::fmt::Write;
@@ -9,7 +10,11 @@ use std::io::BufRead;
 use std::io::BufReader;
 use std::io;
 use std::path::{Path, PathBuf};
-use cargo_repository_hash;
+use cargo_repository_hash::{self, HashScheme, SourceKind};
+use serde_json;
+
+const CRATES_IO_GIT_URL: &str = "https://github.com/rust-lang/crates.io-index";
+const CRATES_IO_SPARSE_URL: &str = "sparse+https://index.crates.io/";
 
 fn get_cargo_manifest_dir() -> PathBuf {
     if let Some(dir) = env::var_os("CARGO_MANIFEST_DIR") {
@@ -39,10 +44,18 @@ impl CargoConfig {
         let manifest_dir = get_cargo_manifest_dir();
         CargoConfig {
             dot_cargo_dir: manifest_dir.join(".cargo"),
-            manifest_dir: manifest_dir,
+            manifest_dir,
         }
     }
 
+    pub fn manifest_dir(&self) -> &Path {
+        &self.manifest_dir
+    }
+
+    pub fn lockfile_path(&self) -> PathBuf {
+        self.manifest_dir.join("Cargo.lock")
+    }
+
     pub fn default_forked_index_repository_path(&self) -> PathBuf {
         self.dot_cargo_dir.join("cargo-lts-local-registry-fork")
     }
@@ -54,6 +67,18 @@ impl CargoConfig {
     }
 
     pub fn set_index_source_override(&self, repo_path: &Path) -> io::Result<()> {
+        let repo_url = Self::repo_path_as_url(repo_path)?;
+        self.set_source_override("lts-repo-local-fork", &format!("registry = '{}'", repo_url))
+    }
+
+    /// For forks that are a plain directory instead of a git repo
+    pub fn set_local_registry_source_override(&self, registry_dir: &Path) -> io::Result<()> {
+        let registry_dir = fs::canonicalize(registry_dir)?;
+        let registry_dir = registry_dir.display().to_string().replace('\\', "/");
+        self.set_source_override("lts-repo-local-dir", &format!("local-registry = '{}'", registry_dir))
+    }
+
+    fn set_source_override(&self, source_name: &str, source_location: &str) -> io::Result<()> {
         let config_path = self.dot_cargo_dir.join("config");
 
         let mut config_toml = if config_path.exists() {
@@ -62,15 +87,13 @@ impl CargoConfig {
             String::new()
         };
 
-        let repo_url = Self::repo_path_as_url(repo_path)?;
-
         write!(&mut config_toml, "# delete this to restore to the default registry
     [source.crates-io]
-    replace-with = 'lts-repo-local-fork'
+    replace-with = '{name}'
 
-    [source.lts-repo-local-fork] # `cargo lts` modified copy of the crates.io registry
-    registry = '{}'
-    ", repo_url).unwrap();
+    [source.{name}] # `cargo lts` modified copy of the crates.io registry
+    {location}
+    ", name = source_name, location = source_location).unwrap();
 
         write(&config_path, config_toml.as_bytes())
     }
@@ -125,35 +148,301 @@ impl CargoConfig {
         Ok(())
     }
 
+    /// `cargo update -p` for just these packages, given as `name@version`
+    pub fn cargo_update_packages(&self, pkgids: &[String]) -> io::Result<()> {
+        let mut cmd = Command::new("cargo");
+        cmd.current_dir(&self.manifest_dir).arg("update");
+        for pkgid in pkgids {
+            cmd.arg("-p").arg(pkgid);
+        }
+        if !cmd.status()?.success() {
+            return io_err("Cargo update of yanked packages failed");
+        }
+        Ok(())
+    }
+
+    pub fn cargo_generate_lockfile_if_needed(&self) -> io::Result<()> {
+        if self.lockfile_path().exists() {
+            return Ok(());
+        }
+        let res = Command::new("cargo")
+            .current_dir(&self.manifest_dir)
+            .arg("generate-lockfile")
+            .status()?;
+
+        if !res.success() {
+            return io_err("Cargo couldn't resolve dependencies using the forked index");
+        }
+        Ok(())
+    }
+
+    /// Downloads all locked crates into Cargo's registry cache
+    pub fn cargo_fetch(&self) -> io::Result<()> {
+        let res = Command::new("cargo")
+            .current_dir(&self.manifest_dir)
+            .arg("fetch")
+            .status()?;
+
+        if !res.success() {
+            return io_err("Cargo fetch failed");
+        }
+        Ok(())
+    }
+
+    /// `.crate` tarballs downloaded by Cargo are in one dir per registry (crates.io, the fork, etc.)
+    pub fn cargo_registry_cache_dirs() -> Vec<PathBuf> {
+        let cargo_home = match get_cargo_home() {
+            Some(p) => p,
+            None => return Vec::new(),
+        };
+        let mut dirs: Vec<_> = match fs::read_dir(cargo_home.join("registry").join("cache")) {
+            Ok(dir) => dir.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_dir()).collect(),
+            Err(_) => return Vec::new(),
+        };
+        dirs.sort();
+        dirs
+    }
+
+    /// Index files Cargo has fetched from crates.io over the sparse protocol. There's one dir per hash of the URL,
+    /// and the hash depends on Cargo's version, so there may be several.
+    pub fn cargo_sparse_index_cache_dirs() -> Vec<PathBuf> {
+        let cargo_home = match get_cargo_home() {
+            Some(p) => p,
+            None => return Vec::new(),
+        };
+        let mut dirs: Vec<_> = match fs::read_dir(cargo_home.join("registry").join("index")) {
+            Ok(dir) => dir.filter_map(|e| e.ok())
+                .filter(|e| e.file_name().to_string_lossy().starts_with("index.crates.io-"))
+                .map(|e| e.path().join(".cache"))
+                .filter(|p| p.is_dir())
+                .collect(),
+            Err(_) => return Vec::new(),
+        };
+        // the installed Cargo's copy is the one that's up to date
+        let current = cargo_repository_hash::registry_dir_name(SourceKind::SparseRegistry, CRATES_IO_SPARSE_URL, Self::cargo_hash_scheme());
+        dirs.sort_by_key(|dir| (!dir.parent().map_or(false, |p| p.ends_with(&current)), dir.clone()));
+        dirs
+    }
+
+    /// Runs `cargo generate-lockfile` in a temporary copy of the project, so that the real `Cargo.lock` stays as-is.
+    /// Returns Cargo's error message if the dependencies can't be resolved.
+    ///
+    /// The copy has the whole workspace, path dependencies outside of it, and `.cargo/config`,
+    /// all at the same places relative to each other.
+    pub fn generate_lockfile_in_scratch_copy(&self) -> io::Result<Result<(), String>> {
+        let metadata = self.metadata()?;
+        let workspace_root = fs::canonicalize(&metadata.workspace_root)?;
+        let mut dirs = vec![workspace_root.clone()];
+        for dep in metadata.packages.iter().flat_map(|p| &p.dependencies) {
+            if let Some(ref path) = dep.path {
+                let path = fs::canonicalize(path)?;
+                if !dirs.iter().any(|d| path.starts_with(d)) {
+                    dirs.push(path);
+                }
+            }
+        }
+        let dot_cargo_dir = fs::canonicalize(&self.dot_cargo_dir)?;
+        if !dirs.iter().any(|d| dot_cargo_dir.starts_with(d)) {
+            dirs.push(dot_cargo_dir.clone());
+        }
+        let mut base = workspace_root.clone();
+        while !dirs.iter().all(|d| d.starts_with(&base)) {
+            if !base.pop() {
+                break;
+            }
+        }
+
+        let scratch_dir = unique_temp_dir("cargo-lts-verify")?;
+        // `.cargo` also has the fork and its summary, which are big, and are used from where they are
+        let skip = [
+            workspace_root.join("target"),
+            workspace_root.join(".git"),
+            workspace_root.join("Cargo.lock"),
+            dot_cargo_dir.clone(),
+        ];
+        let res = dirs.iter()
+            .filter(|dir| **dir != dot_cargo_dir)
+            .map(|dir| copy_dir_except(dir, &scratch_dir.join(dir.strip_prefix(&base).unwrap_or(dir)), &skip))
+            .collect::<io::Result<Vec<_>>>()
+            .and_then(|_| copy_cargo_config_files(&dot_cargo_dir, &scratch_dir.join(dot_cargo_dir.strip_prefix(&base).unwrap_or(&dot_cargo_dir))))
+            .and_then(|_| {
+                Command::new("cargo")
+                    .current_dir(scratch_dir.join(workspace_root.strip_prefix(&base).unwrap_or(&workspace_root)))
+                    .arg("generate-lockfile")
+                    .output()
+            });
+        let _ = fs::remove_dir_all(&scratch_dir);
+
+        let out = res?;
+        if out.status.success() {
+            Ok(Ok(()))
+        } else {
+            Ok(Err(String::from_utf8_lossy(&out.stderr).into_owned()))
+        }
+    }
+
+    /// Packages of the workspace, with dependencies as written in their `Cargo.toml`
+    pub fn workspace_packages(&self) -> io::Result<Vec<WorkspacePackage>> {
+        Ok(self.metadata()?.packages)
+    }
+
+    fn metadata(&self) -> io::Result<Metadata> {
+        let out = Command::new("cargo")
+            .current_dir(&self.manifest_dir)
+            .arg("metadata")
+            .arg("--no-deps")
+            .arg("--format-version").arg("1")
+            .output()?;
+        if !out.status.success() {
+            return io_err("Cargo couldn't read the project's metadata");
+        }
+        match serde_json::from_slice::<Metadata>(&out.stdout) {
+            Ok(m) => Ok(m),
+            Err(e) => io_err(&format!("Can't parse cargo metadata: {}", e)),
+        }
+    }
+
+    /// Cargo's git clone of crates.io index, made by this or any other version of Cargo
     pub fn cargo_private_crates_io_git_repo_path() -> Option<PathBuf> {
         let cargo_home = match get_cargo_home() {
             Some(p) => p,
             None => return None,
         };
         assert!(cargo_home.is_absolute());
-        let path = cargo_home.join("registry").join("index").join("github.com-1ecc6299db9ec823");
-        if path.exists() {
-            Some(path)
-        } else {
-            None
-        }
+        let current = Self::cargo_hash_scheme();
+        let other = if current == HashScheme::SipHash { HashScheme::StableHash } else { HashScheme::SipHash };
+        [current, other].iter()
+            .map(|&scheme| cargo_repository_hash::registry_dir_name(SourceKind::Registry, CRATES_IO_GIT_URL, scheme))
+            .map(|name| cargo_home.join("registry").join("index").join(name))
+            .find(|path| path.exists())
     }
 
+    /// Where the installed version of Cargo keeps its clone of a git registry (which may not exist yet)
     pub fn cargo_private_custom_git_repo_path(repo_path: &Path) -> Option<PathBuf> {
         let cargo_home = match get_cargo_home() {
             Some(p) => p,
             None => return None,
         };
         assert!(cargo_home.is_absolute());
-        let url = match Self::repo_path_as_url(repo_path) {
+        let mut url = match Self::repo_path_as_url(repo_path) {
             Ok(p) => p,
             Err(_) => return None,
         };
-        let hash = cargo_repository_hash::short_hash(&url);
-        Some(cargo_home.join("registry").join("index").join(format!("-{}", hash)))
+        // Cargo uses the repo's dir for `.git`
+        if url.ends_with("/.git") {
+            let len = url.len() - ".git".len();
+            url.truncate(len);
+        }
+        let name = cargo_repository_hash::registry_dir_name(SourceKind::Registry, &url, Self::cargo_hash_scheme());
+        Some(cargo_home.join("registry").join("index").join(name))
+    }
+
+    /// Cache dir names depend on Cargo's version. Assumes a recent Cargo if the version is unknown.
+    fn cargo_hash_scheme() -> HashScheme {
+        let out = match Command::new("cargo").arg("--version").output() {
+            Ok(ref out) if out.status.success() => String::from_utf8_lossy(&out.stdout).into_owned(),
+            _ => return HashScheme::StableHash,
+        };
+        // e.g. "cargo 1.84.0 (66221abde 2024-11-19)" or "cargo 0.26.0-nightly"
+        let version = out.split_whitespace().nth(1).unwrap_or("");
+        let mut parts = version.split(|c| c == '.' || c == '-').map(|p| p.parse::<u32>().unwrap_or(0));
+        let major = parts.next().unwrap_or(1);
+        let minor = parts.next().unwrap_or(0);
+        if major == 0 {
+            return HashScheme::SipHash; // Cargo versioned separately from Rust
+        }
+        HashScheme::for_cargo_version(major, minor)
     }
 }
 
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<WorkspacePackage>,
+    workspace_root: PathBuf,
+}
+
+#[derive(Deserialize)]
+pub struct WorkspacePackage {
+    pub name: String,
+    pub version: String,
+    pub dependencies: Vec<ManifestDependency>,
+}
+
+#[derive(Deserialize)]
+pub struct ManifestDependency {
+    /// Real name, even if renamed
+    pub name: String,
+    pub req: String,
+    #[serde(default)]
+    pub optional: bool,
+    pub source: Option<String>,
+    /// Dir of a path dependency
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+impl ManifestDependency {
+    pub fn is_from_crates_io(&self) -> bool {
+        match self.source {
+            Some(ref s) => is_crates_io_source(s),
+            None => false,
+        }
+    }
+}
+
+fn copy_dir_except(src: &Path, dest: &Path, skip: &[PathBuf]) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let path = entry.path();
+        if skip.contains(&path) {
+            continue;
+        }
+        let dest_path = dest.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            copy_symlink(&path, &dest_path)?;
+        } else if file_type.is_dir() {
+            copy_dir_except(&path, &dest_path, skip)?;
+        } else {
+            fs::copy(&path, &dest_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// `config` and `config.toml`, without anything else that is in `.cargo`
+fn copy_cargo_config_files(dot_cargo_dir: &Path, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(dot_cargo_dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with("config") && !entry.file_type()?.is_dir() {
+            fs::copy(entry.path(), dest.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Makes the same link, so that relative links point into the copy
+#[cfg(unix)]
+fn copy_symlink(src: &Path, dest: &Path) -> io::Result<()> {
+    ::std::os::unix::fs::symlink(fs::read_link(src)?, dest)
+}
+
+/// Links may need admin rights on Windows, so files they point to are copied instead.
+/// Links to directories are skipped.
+#[cfg(not(unix))]
+fn copy_symlink(src: &Path, dest: &Path) -> io::Result<()> {
+    match fs::metadata(src) {
+        Ok(ref m) if m.is_file() => fs::copy(src, dest).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// Source id of crates.io, as used in `Cargo.lock` and metadata (git or sparse protocol)
+pub fn is_crates_io_source(source: &str) -> bool {
+    source == format!("registry+{}", CRATES_IO_GIT_URL) || source == CRATES_IO_SPARSE_URL
+}
 
 #[allow(deprecated)]
 fn get_cargo_home() -> Option<PathBuf> {
@@ -1,6 +1,33 @@
 #[allow(deprecated)]
 use std::hash::{Hash, Hasher, SipHasher};
 
+/// How Cargo hashes source ids to name its directories in `~/.cargo/registry`
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HashScheme {
+    /// `SipHasher` (SipHash-2-4) of the derived `Hash`, used up to Cargo 1.84
+    SipHash,
+    /// `rustc-stable-hash` (SipHash-1-3, 128-bit folded to 64), used since Cargo 1.85
+    StableHash,
+}
+
+impl HashScheme {
+    pub fn for_cargo_version(major: u32, minor: u32) -> Self {
+        if major > 1 || minor >= 85 {
+            HashScheme::StableHash
+        } else {
+            HashScheme::SipHash
+        }
+    }
+}
+
+/// Kind of registry, in the order of Cargo's `SourceKind`, because its discriminant is hashed
+#[derive(Copy, Clone, Debug)]
+pub enum SourceKind {
+    /// Git index
+    Registry,
+    SparseRegistry,
+}
+
 #[derive(Hash)]
 struct CargoCompatibleSourceId<'a> {
     kind: Kind,
@@ -12,17 +39,44 @@ enum Kind {
     _1,
     _2,
     Registry,
+    SparseRegistry,
 }
 
-#[allow(deprecated)]
+/// Hash of a git registry's URL, the way Cargo did it before 1.85
+#[allow(dead_code)]
 pub fn short_hash(url: &str) -> String {
-    let hashable = CargoCompatibleSourceId {
-        url: url,
-        kind: Kind::Registry,
+    source_short_hash(SourceKind::Registry, url, HashScheme::SipHash)
+}
+
+#[allow(deprecated)]
+pub fn source_short_hash(kind: SourceKind, url: &str, scheme: HashScheme) -> String {
+    let num = match scheme {
+        HashScheme::SipHash => {
+            let hashable = CargoCompatibleSourceId {
+                url: url,
+                kind: match kind {
+                    SourceKind::Registry => Kind::Registry,
+                    SourceKind::SparseRegistry => Kind::SparseRegistry,
+                },
+            };
+            let mut hasher = SipHasher::new_with_keys(0, 0);
+            hashable.hash(&mut hasher);
+            hasher.finish()
+        },
+        HashScheme::StableHash => {
+            let discriminant = match kind {
+                SourceKind::Registry => Kind::Registry as u8,
+                SourceKind::SparseRegistry => Kind::SparseRegistry as u8,
+            };
+            // small `isize` is hashed as one byte, and `str` is terminated with 0xFF
+            let mut data = Vec::with_capacity(url.len() + 2);
+            data.push(discriminant);
+            data.extend_from_slice(url.as_bytes());
+            data.push(0xFF);
+            let (l, h) = sip13_128(&data);
+            l.wrapping_mul(3).wrapping_add(h)
+        },
     };
-    let mut hasher = SipHasher::new_with_keys(0, 0);
-    hashable.hash(&mut hasher);
-    let num = hasher.finish();
     format!("{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
         (num >> 0) as u8,
         (num >> 8) as u8,
@@ -35,7 +89,88 @@ pub fn short_hash(url: &str) -> String {
     )
 }
 
+/// Name of the dir in `~/.cargo/registry/index` (and `cache`, `src`), e.g. `index.crates.io-1949cf8c6b5b557f`
+pub fn registry_dir_name(kind: SourceKind, url: &str, scheme: HashScheme) -> String {
+    format!("{}-{}", url_host(url), source_short_hash(kind, url, scheme))
+}
+
+/// Empty for `file://` URLs
+fn url_host(url: &str) -> &str {
+    let rest = match url.find("://") {
+        Some(pos) => &url[pos + 3..],
+        None => return "",
+    };
+    let authority = rest.split('/').next().unwrap_or("");
+    let host = authority.rsplit('@').next().unwrap_or(authority);
+    host.split(':').next().unwrap_or(host)
+}
+
+/// SipHash-1-3 with 128-bit output and zero keys, as in `rustc-stable-hash`
+fn sip13_128(data: &[u8]) -> (u64, u64) {
+    let mut v = [0x736f6d6570736575u64, 0x646f72616e646f6d ^ 0xee, 0x6c7967656e657261, 0x7465646279746573];
+    let full_len = data.len() / 8 * 8;
+    for chunk in data[..full_len].chunks(8) {
+        let m = le_u64(chunk);
+        v[3] ^= m;
+        sip_round(&mut v);
+        v[0] ^= m;
+    }
+    let b = ((data.len() as u64 & 0xff) << 56) | le_u64(&data[full_len..]);
+    v[3] ^= b;
+    sip_round(&mut v);
+    v[0] ^= b;
+
+    v[2] ^= 0xee;
+    for _ in 0..3 {
+        sip_round(&mut v);
+    }
+    let l = v[0] ^ v[1] ^ v[2] ^ v[3];
+    v[1] ^= 0xdd;
+    for _ in 0..3 {
+        sip_round(&mut v);
+    }
+    (l, v[0] ^ v[1] ^ v[2] ^ v[3])
+}
+
+/// Up to 8 bytes, zero-padded
+fn le_u64(bytes: &[u8]) -> u64 {
+    bytes.iter().enumerate().fold(0, |n, (i, &b)| n | (u64::from(b) << (8 * i)))
+}
+
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13);
+    v[1] ^= v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16);
+    v[3] ^= v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21);
+    v[3] ^= v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17);
+    v[1] ^= v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
 #[test]
 fn hash() {
     assert_eq!("1ecc6299db9ec823", short_hash("https://github.com/rust-lang/crates.io-index"));
 }
+
+#[test]
+fn dir_names_of_cargo_versions() {
+    let old = HashScheme::for_cargo_version(1, 84);
+    let new = HashScheme::for_cargo_version(1, 85);
+    assert_eq!(HashScheme::SipHash, HashScheme::for_cargo_version(1, 19));
+    assert_eq!(HashScheme::StableHash, HashScheme::for_cargo_version(1, 95));
+
+    assert_eq!("github.com-1ecc6299db9ec823", registry_dir_name(SourceKind::Registry, "https://github.com/rust-lang/crates.io-index", old));
+    assert_eq!("index.crates.io-6f17d22bba15001f", registry_dir_name(SourceKind::SparseRegistry, "sparse+https://index.crates.io/", old));
+
+    assert_eq!("github.com-25cdd57fae9f0462", registry_dir_name(SourceKind::Registry, "https://github.com/rust-lang/crates.io-index", new));
+    assert_eq!("index.crates.io-1949cf8c6b5b557f", registry_dir_name(SourceKind::SparseRegistry, "sparse+https://index.crates.io/", new));
+    // made by Cargo 1.95 for a fork
+    assert_eq!("-eb7305573e0ac501", registry_dir_name(SourceKind::Registry, "file:///tmp/proj/.cargo/cargo-lts-local-registry-fork/", new));
+}
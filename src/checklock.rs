@@ -0,0 +1,44 @@
+use io_err;
+use lockfile::Lockfile;
+use regfork::YankSpec;
+use std::io;
+use std::path::Path;
+
+/// A yank and where it came from, for the report
+pub struct PolicyRule {
+    pub spec: YankSpec,
+    /// As written by the user
+    pub text: String,
+    pub origin: String,
+}
+
+/// Reports crates.io packages in `Cargo.lock` that the policy would yank.
+/// Doesn't need the fork, so it's cheap enough to run in CI.
+///
+/// Returns number of forbidden packages.
+pub fn check_lockfile(lockfile_path: &Path, rules: &[PolicyRule]) -> io::Result<usize> {
+    if !lockfile_path.exists() {
+        return io_err("There's no Cargo.lock to check. Run `cargo generate-lockfile` first");
+    }
+    let lock = Lockfile::load(lockfile_path)?;
+    let packages = lock.crates_io_packages();
+
+    let mut forbidden = 0;
+    for pkg in &packages {
+        let name = pkg.name.to_lowercase();
+        let matching: Vec<_> = rules.iter()
+            .filter(|r| r.spec.crate_name.to_lowercase() == name && r.spec.matches_version(&pkg.version))
+            .collect();
+        if matching.is_empty() {
+            continue;
+        }
+        forbidden += 1;
+        println!("{} {} is forbidden by:", pkg.name, pkg.version);
+        for rule in matching {
+            println!("    {} ({})", rule.text, rule.origin);
+        }
+    }
+
+    println!("Checked {} packages from crates.io, {} forbidden", packages.len(), forbidden);
+    Ok(forbidden)
+}
@@ -0,0 +1,58 @@
+use regfork::{CrateVersion, ForkedRegistryIndex};
+use serde_json;
+use std::io;
+
+/// A version whose index entry in the fork isn't the same as on crates.io
+#[derive(Serialize)]
+pub struct Change {
+    #[serde(rename = "crate")]
+    pub crate_name: String,
+    pub version: String,
+    /// `yanked`, `unyanked`, `modified`, `added` (only in the fork) or `removed` (only on crates.io)
+    pub change: &'static str,
+}
+
+/// Every version that differs between the fork's working tree and the last fetched crates.io commit
+pub fn changes(fork: &ForkedRegistryIndex) -> io::Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    let mut crate_names = fork.crates_changed_since_upstream()?;
+    crate_names.sort();
+    crate_names.dedup();
+    for crate_name in crate_names {
+        let forked = fork.crate_index_entries(&crate_name).unwrap_or_default();
+        let upstream = fork.upstream_crate_index_entries(&crate_name)?;
+        for f in &forked {
+            let change = match upstream.iter().find(|u| u.0.vers == f.0.vers) {
+                None => "added",
+                Some(u) if !u.0.yanked && f.0.yanked => "yanked",
+                Some(u) if u.0.yanked && !f.0.yanked => "unyanked",
+                // yanking rewrites the line, so compare what the entries say, not their bytes
+                Some(u) if !same_except_yanked(&u.0, &f.0) => "modified",
+                Some(_) => continue,
+            };
+            changes.push(Change { crate_name: f.0.name.clone(), version: f.0.vers.clone(), change });
+        }
+        for u in upstream.iter().filter(|u| !forked.iter().any(|f| f.0.vers == u.0.vers)) {
+            changes.push(Change { crate_name: u.0.name.clone(), version: u.0.vers.clone(), change: "removed" });
+        }
+    }
+    Ok(changes)
+}
+
+fn same_except_yanked(a: &CrateVersion, b: &CrateVersion) -> bool {
+    a.name == b.name && a.cksum == b.cksum && a.links == b.links && a.deps == b.deps && a.features == b.features && a.rust_version == b.rust_version
+}
+
+pub fn print_changes(changes: &[Change], json: bool) {
+    if json {
+        println!("{}", serde_json::to_string_pretty(changes).unwrap());
+        return;
+    }
+    if changes.is_empty() {
+        println!("The fork is the same as crates.io");
+        return;
+    }
+    for c in changes {
+        println!("{:<10} {} {}", c.change, c.crate_name, c.version);
+    }
+}
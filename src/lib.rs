@@ -13,10 +13,28 @@ use std::io;
 use std::fs;
 use std::env;
 use std::process::Command;
-use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
 
+mod apply;
+mod ban;
 mod cargo;
 mod cargo_repository_hash;
+mod checklock;
+mod diff;
+mod lockfile;
+mod minimal;
+mod profiles;
+mod rdeps;
+use profiles::{Defaults, Profile};
+mod sha256;
+mod sparse_cache;
+mod status;
+mod summary;
+mod unsatisfiable;
+mod vendor;
+mod verify;
+mod versions;
 
 mod regfork;
 use regfork::ForkedRegistryIndex;
@@ -28,24 +46,167 @@ pub fn cli_run() -> io::Result<()> {
     match parse_args() {
         Op::Exit => return Ok(()),
         Op::Fail => std::process::exit(1),
-        Op::Setup => {
-            setup_if_needed(&cargo_config)?;
+        Op::Setup { profile, defaults, default_yanks, git } => {
+            let defaults_data = Defaults::load_or_bundled(defaults.as_ref().map(|p| p.as_path()))?;
+            let chosen_profile = match profile {
+                Some(ref name) => match defaults_data.find(name) {
+                    Some(p) => p,
+                    None => return io_err(&format!("There's no profile '{}'. See `cargo lts profiles`", name)),
+                },
+                None => defaults_data.default_profile(),
+            };
+            let fork_path = cargo_config.default_forked_index_repository_path();
+            let fork = if git { ForkedRegistryIndex::new(fork_path) } else { ForkedRegistryIndex::new_directory(fork_path) };
+            if !git && !fork.is_directory() {
+                return io_err("The fork already uses git. Run `cargo lts reset` first");
+            }
+            if !default_yanks {
+                setup_fork(&cargo_config, fork, &Defaults::bundled(), None)?;
+                return Ok(());
+            }
+            let fork = setup_fork(&cargo_config, fork, &defaults_data, Some(chosen_profile))?;
+            if profile.is_some() || defaults.is_some() {
+                // on first setup this is a no-op, but an existing fork needs it applied
+                fork.set_default_yanks(&defaults_data, chosen_profile)?;
+            }
+        },
+        Op::Profiles(defaults) => {
+            let defaults = Defaults::load_or_bundled(defaults.as_ref().map(|p| p.as_path()))?;
+            for profile in &defaults.profiles {
+                let default = if profile.name == defaults.default_profile { " (default)" } else { "" };
+                println!("{}{}", profile.name, default);
+                if !profile.description.is_empty() {
+                    println!("    {}", profile.description);
+                }
+                println!("    {} yanks", defaults.yanked(profile).len());
+            }
+        },
+        Op::Ban { specs, propagate, default_yanks } => {
+            if specs.is_empty() {
+                eprintln!("Nothing to ban");
+                std::process::exit(1);
+            }
+            let fork = setup_with_bundled_defaults(&cargo_config, default_yanks)?;
+            ban::ban(&fork, &specs, propagate)?;
+            warn_unsatisfiable(&cargo_config, &fork, &specs);
+        },
+        Op::CheckLock { specs, spec_file, profile, defaults } => {
+            let rules = match check_lock_rules(specs, spec_file, profile, defaults)? {
+                Some(rules) => rules,
+                None => std::process::exit(1),
+            };
+            if checklock::check_lockfile(&cargo_config.lockfile_path(), &rules)? > 0 {
+                std::process::exit(1);
+            }
+        },
+        Op::Diff { json } => {
+            let fork = existing_fork(&cargo_config)?;
+            diff::print_changes(&diff::changes(&fork)?, json);
+        },
+        Op::Defaults { revert: false } => print_default_yanks(&cargo_config)?,
+        Op::Defaults { revert: true } => {
+            let fork = existing_fork(&cargo_config)?;
+            fork.revert_default_yanks()?;
+        },
+        Op::Minimal { revert: true, .. } => {
+            let fork = existing_fork(&cargo_config)?;
+            fork.revert_minimal_yanks()?;
+            println!("Versions yanked by `cargo lts minimal` are back. Run `cargo update` to use them");
+        },
+        Op::Minimal { default_yanks, revert: false } => {
+            let fork = setup_with_bundled_defaults(&cargo_config, default_yanks)?;
+            let to_yank = minimal::minimal_versions_yanks(&cargo_config, &fork)?;
+            fork.set_minimal_yanks(&to_yank)?;
+            println!("Run `cargo update` to pick the minimal versions. To undo, run `cargo lts minimal --revert`");
+        },
+        Op::Pin(specs) => {
+            if specs.is_empty() {
+                let fork = existing_fork(&cargo_config)?;
+                print_pins(&fork)?;
+            } else {
+                let fork = setup_with_bundled_defaults(&cargo_config, true)?;
+                fork.pin(&specs)?;
+            }
+        },
+        Op::Unpin(crate_names) => {
+            let fork = existing_fork(&cargo_config)?;
+            fork.unpin(&crate_names)?;
         },
         Op::Prefetch => {
             fetch_registry(&cargo_config)?
         },
+        Op::Status => {
+            let fork = existing_fork(&cargo_config)?;
+            status::print_status(&cargo_config, &fork)?;
+        },
         Op::Update => {
             fetch_registry(&cargo_config)?;
             cargo_config.cargo_update_from_current_index()?;
         },
         Op::Reset => delete_local_fork(&cargo_config)?,
-        Op::Yank(specs) => {
+        Op::Vendor(dest) => {
+            let fork = setup_with_bundled_defaults(&cargo_config, true)?;
+            let dest = dest.unwrap_or_else(|| cargo_config.manifest_dir().join("lts-vendor"));
+            vendor::vendor(&cargo_config, &fork, &dest)?;
+        },
+        Op::Versions(spec) => {
+            let fork = existing_fork(&cargo_config)?;
+            versions::print_versions(&fork, &spec)?;
+        },
+        Op::Rdeps { spec, locked } => {
+            let fork = existing_fork(&cargo_config)?;
+            let lock = if locked {
+                let lockfile_path = cargo_config.lockfile_path();
+                if !lockfile_path.exists() {
+                    return io_err("There's no Cargo.lock. Run `cargo generate-lockfile` first");
+                }
+                Some(lockfile::Lockfile::load(&lockfile_path)?)
+            } else {
+                None
+            };
+            rdeps::print_dependents(&spec, &rdeps::dependents(&fork, &spec, lock.as_ref())?);
+        },
+        Op::VerifyCache => {
+            let fork = existing_fork(&cargo_config)?;
+            if verify::verify_cache(&cargo_config, &fork)? > 0 {
+                std::process::exit(1);
+            }
+        },
+        Op::Yank { specs, default_yanks, verify, apply } => {
             if specs.is_empty() {
                 eprintln!("Nothing to change");
                 std::process::exit(1);
             }
-            let fork = setup_if_needed(&cargo_config)?;
-            fork.set_yanked_state(&specs, true)?
+            let fork = setup_with_bundled_defaults(&cargo_config, default_yanks)?;
+            let changed;
+            {
+                // so that the rollback can't undo another process's changes
+                let _lock = fork.lock()?;
+                // directory forks make a copy of the index for this
+                let before = if verify { Some(fork.head_commit()?) } else { None };
+                changed = fork.set_yanked_state(&specs, true)?;
+                if let Some(before) = before {
+                    println!("Checking that the project's dependencies still resolve");
+                    match cargo_config.generate_lockfile_in_scratch_copy() {
+                        Ok(Ok(())) => {},
+                        Ok(Err(cargo_error)) => {
+                            eprint!("{}", cargo_error);
+                            fork.reset_to(&before)?;
+                            return io_err("Dependencies can't be resolved after this change, so it has been rolled back");
+                        },
+                        Err(e) => {
+                            fork.reset_to(&before)?;
+                            return io_err(&format!("Can't check if dependencies resolve ({}), so the change has been rolled back", e));
+                        },
+                    }
+                }
+            }
+            if specs.iter().any(|s| s.yank) {
+                warn_unsatisfiable(&cargo_config, &fork, &specs);
+                if apply {
+                    apply::update_yanked_locked_packages(&cargo_config, &changed)?;
+                }
+            }
         }
     }
 
@@ -53,11 +214,59 @@ pub fn cli_run() -> io::Result<()> {
 }
 
 enum Op {
+    Ban {
+        specs: Vec<YankSpec>,
+        propagate: bool,
+        default_yanks: bool,
+    },
+    CheckLock {
+        specs: Vec<String>,
+        spec_file: Option<PathBuf>,
+        /// `None` for no default yanks
+        profile: Option<String>,
+        defaults: Option<PathBuf>,
+    },
+    Defaults {
+        revert: bool,
+    },
+    Diff {
+        json: bool,
+    },
     Reset,
     Prefetch,
-    Setup,
+    Minimal {
+        default_yanks: bool,
+        revert: bool,
+    },
+    /// Lists pins if empty
+    Pin(Vec<YankSpec>),
+    Unpin(Vec<String>),
+    Profiles(Option<PathBuf>),
+    Rdeps {
+        spec: YankSpec,
+        /// Only packages in `Cargo.lock`
+        locked: bool,
+    },
+    Setup {
+        profile: Option<String>,
+        defaults: Option<PathBuf>,
+        default_yanks: bool,
+        /// `false` to make the fork a plain directory
+        git: bool,
+    },
+    Status,
     Update,
-    Yank(Vec<YankSpec>),
+    Vendor(Option<PathBuf>),
+    VerifyCache,
+    Versions(YankSpec),
+    Yank {
+        specs: Vec<YankSpec>,
+        default_yanks: bool,
+        /// Roll back if `Cargo.lock` can't be generated
+        verify: bool,
+        /// Update yanked packages in `Cargo.lock`
+        apply: bool,
+    },
     Exit,
     Fail,
 }
@@ -73,14 +282,149 @@ fn parse_args() -> Op {
     };
 
     match cmd.as_str() {
-        "setup" => Op::Setup,
+        "setup" => {
+            let mut args: Vec<_> = args.collect();
+            let default_yanks = !take_flag(&mut args, "--no-default-yanks");
+            let git = !take_flag(&mut args, "--no-git");
+            let (profile, defaults) = match (take_option(&mut args, "--profile"), take_option(&mut args, "--defaults")) {
+                (Ok(profile), Ok(defaults)) => (profile, defaults),
+                _ => return Op::Fail,
+            };
+            if !default_yanks && (profile.is_some() || defaults.is_some()) {
+                eprintln!("--no-default-yanks can't be used with a profile");
+                return Op::Fail;
+            }
+            if !no_more_args(&args) {
+                return Op::Fail;
+            }
+            Op::Setup {
+                profile,
+                defaults: defaults.map(PathBuf::from),
+                default_yanks,
+                git,
+            }
+        },
+        "ban" => {
+            let mut args: Vec<_> = args.collect();
+            let propagate = take_flag(&mut args, "--propagate");
+            let default_yanks = !take_flag(&mut args, "--no-default-yanks");
+            Op::Ban {
+                specs: parse_yankspecs(args.into_iter(), true),
+                propagate,
+                default_yanks,
+            }
+        },
+        "check-lock" => {
+            let mut args: Vec<_> = args.collect();
+            let default_yanks = !take_flag(&mut args, "--no-default-yanks");
+            match (take_option(&mut args, "--specs"), take_option(&mut args, "--profile"), take_option(&mut args, "--defaults")) {
+                (Ok(spec_file), Ok(profile), Ok(defaults)) => {
+                    if !default_yanks && (profile.is_some() || defaults.is_some()) {
+                        eprintln!("--no-default-yanks can't be used with a profile");
+                        return Op::Fail;
+                    }
+                    if let Some(arg) = args.iter().find(|arg| arg.starts_with("--")) {
+                        eprintln!("Unknown arg: {}", arg);
+                        return Op::Fail;
+                    }
+                    Op::CheckLock {
+                        specs: args,
+                        spec_file: spec_file.map(PathBuf::from),
+                        profile: if default_yanks { Some(profile.unwrap_or_default()) } else { None },
+                        defaults: defaults.map(PathBuf::from),
+                    }
+                },
+                _ => Op::Fail,
+            }
+        },
+        "defaults" => {
+            let mut args: Vec<_> = args.collect();
+            let revert = take_flag(&mut args, "--revert");
+            if no_more_args(&args) { Op::Defaults { revert } } else { Op::Fail }
+        },
+        "diff" => {
+            let mut args: Vec<_> = args.collect();
+            match take_option(&mut args, "--format") {
+                Ok(_) if !no_more_args(&args) => Op::Fail,
+                Ok(None) => Op::Diff { json: false },
+                Ok(Some(ref format)) if format == "human" => Op::Diff { json: false },
+                Ok(Some(ref format)) if format == "json" => Op::Diff { json: true },
+                Ok(Some(format)) => {
+                    eprintln!("Unknown format: {}. Use human or json", format);
+                    Op::Fail
+                },
+                Err(()) => Op::Fail,
+            }
+        },
+        "minimal" => {
+            let mut args: Vec<_> = args.collect();
+            let default_yanks = !take_flag(&mut args, "--no-default-yanks");
+            let revert = take_flag(&mut args, "--revert");
+            if no_more_args(&args) { Op::Minimal { default_yanks, revert } } else { Op::Fail }
+        },
+        "pin" => {
+            let args: Vec<_> = args.collect();
+            let num_args = args.len();
+            let specs = parse_yankspecs(args.into_iter(), true);
+            if specs.len() != num_args {
+                return Op::Fail;
+            }
+            Op::Pin(specs)
+        },
+        "unpin" => {
+            let args: Vec<_> = args.collect();
+            if args.is_empty() || args.iter().any(|a| !a.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')) {
+                eprintln!("Specify names of pinned crates, without versions");
+                return Op::Fail;
+            }
+            Op::Unpin(args)
+        },
+        "profiles" => {
+            let mut args: Vec<_> = args.collect();
+            match take_option(&mut args, "--defaults") {
+                Ok(ref defaults) if no_more_args(&args) => Op::Profiles(defaults.as_ref().map(PathBuf::from)),
+                _ => Op::Fail,
+            }
+        },
         "prefetch" => Op::Prefetch,
+        "status" => Op::Status,
         "update" => Op::Update,
-        "yank" => {
-            Op::Yank(parse_yankspecs(args, true))
+        "vendor" => Op::Vendor(args.next().map(PathBuf::from)),
+        "verify-cache" => Op::VerifyCache,
+        "versions" => {
+            let args: Vec<_> = args.collect();
+            if args.len() != 1 {
+                eprintln!("Specify one crate, optionally with a semver range");
+                return Op::Fail;
+            }
+            match parse_crate_range(&args[0]) {
+                Some(spec) => Op::Versions(spec),
+                None => Op::Fail,
+            }
         },
-        "unyank" => {
-            Op::Yank(parse_yankspecs(args, false))
+        "rdeps" => {
+            let mut args: Vec<_> = args.collect();
+            let locked = take_flag(&mut args, "--locked");
+            if args.len() != 1 || args[0].starts_with("--") {
+                eprintln!("Specify one crate, optionally with a semver range");
+                return Op::Fail;
+            }
+            match parse_crate_range(&args[0]) {
+                Some(spec) => Op::Rdeps { spec, locked },
+                None => Op::Fail,
+            }
+        },
+        "yank" | "unyank" => {
+            let mut args: Vec<_> = args.collect();
+            let default_yanks = !take_flag(&mut args, "--no-default-yanks");
+            let verify = take_flag(&mut args, "--verify");
+            let apply = take_flag(&mut args, "--apply");
+            Op::Yank {
+                specs: parse_yankspecs(args.into_iter(), cmd == "yank"),
+                default_yanks,
+                verify,
+                apply,
+            }
         },
         "reset" | "unset" => {
             Op::Reset
@@ -114,21 +458,119 @@ Remove any crate from the registry:
 SPEC is crate's name followed by a semver range without a space in between,
 e.g. "pkg-config<=0.3.6", "semver>=0.11", or "openssl=0.0.1", or "file*".
 SPEC must be in quotes. Run `cargo update` to apply changes.
+Add --verify to undo the change if the project's dependencies can't be resolved.
+Add --apply to update only the yanked packages in Cargo.lock.
+
+Set up the registry fork with yanks for an old compiler:
+    cargo lts setup --profile rust-1.31
+
+Make the fork without git, from Cargo's cache of the crates.io index:
+    cargo lts setup --no-git
+
+List available profiles:
+    cargo lts profiles
+
+Use your own profiles instead of the built-in ones:
+    cargo lts setup --defaults profiles.txt --profile NAME
+
+Don't yank anything by default when setting up the fork:
+    cargo lts setup --no-default-yanks
+
+List default yanks, or bring back everything they yanked:
+    cargo lts defaults [--revert]
+
+Yank crates, and also every version of other crates that can't work without them:
+    cargo lts ban "SPEC" --propagate
 
 Bring back yanked crate:
     cargo lts unyank "SPEC"
 
+Yank every version of a crate except these, list pins, or bring back what a pin has yanked:
+    cargo lts pin "CRATE=VERSION"
+    cargo lts pin
+    cargo lts unpin CRATE
+
+List versions of a crate, with their yanked state and minimum Rust version:
+    cargo lts versions "CRATE[RANGE]"
+
+Yank all but the lowest versions that satisfy the project's dependencies, to test minimal versions:
+    cargo lts minimal [--revert]
+
+List versions of other crates that can't work without these versions of a crate:
+    cargo lts rdeps "CRATE[RANGE]" [--locked]
+
+List every version that the fork changes compared to crates.io:
+    cargo lts diff [--format json]
+
+Show which locked packages are yanked in the fork or on crates.io:
+    cargo lts status
+
 Pull new crate versions from the crates.io registry:
     cargo lts update
 
 When using a patched registry `cargo update` doesn't fetch from crates.io.
 
+Copy all locked crates into a local registry for offline builds:
+    cargo lts vendor [DIR]
+
+Check that Cargo's downloaded crates match checksums published on crates.io:
+    cargo lts verify-cache
+
+Fail if Cargo.lock has any version forbidden by the yanks (without making a fork):
+    cargo lts check-lock ["SPEC"...] [--specs FILE] [--profile NAME] [--no-default-yanks]
+
 Reset back to normal crates.io registry:
     cargo lts reset
 "#
 );
 }
 
+/// Crate name with an optional semver range, matching all versions if there's no range
+fn parse_crate_range(arg: &str) -> Option<YankSpec> {
+    let spec = if arg.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') { format!("{}*", arg) } else { arg.to_string() };
+    parse_yankspecs(Some(spec).into_iter(), true).pop()
+}
+
+/// Removes `--name VALUE` or `--name=VALUE` from the args
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, ()> {
+    let prefix = format!("{}=", name);
+    let pos = match args.iter().position(|arg| arg == name || arg.starts_with(&prefix)) {
+        Some(pos) => pos,
+        None => return Ok(None),
+    };
+    let arg = args.remove(pos);
+    if arg != name {
+        return Ok(Some(arg[prefix.len()..].to_string()));
+    }
+    if pos < args.len() {
+        Ok(Some(args.remove(pos)))
+    } else {
+        eprintln!("{} needs a value", name);
+        Err(())
+    }
+}
+
+/// Removes `--name` from the args
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|arg| arg == name) {
+        Some(pos) => {
+            args.remove(pos);
+            true
+        },
+        None => false,
+    }
+}
+
+fn no_more_args(args: &[String]) -> bool {
+    match args.first() {
+        Some(arg) => {
+            eprintln!("Unknown arg: {}", arg);
+            false
+        },
+        None => true,
+    }
+}
+
 fn parse_yankspecs<I>(args: I, yank: bool) -> Vec<YankSpec> where I: Iterator<Item=String> {
     args.filter_map(|arg| {
         let pos = match arg.as_bytes().iter().position(|&c| !(c as char).is_alphanumeric() && c != b'_' && c != b'-') {
@@ -159,14 +601,158 @@ fn parse_yankspecs<I>(args: I, yank: bool) -> Vec<YankSpec> where I: Iterator<It
     }).collect()
 }
 
-fn setup_if_needed(cargo: &CargoConfig) -> io::Result<ForkedRegistryIndex> {
+/// Yanks from the command line, a file with one spec per line, and a profile (empty name for the default one).
+/// `None` if any spec is invalid.
+fn check_lock_rules(specs: Vec<String>, spec_file: Option<PathBuf>, profile: Option<String>, defaults: Option<PathBuf>) -> io::Result<Option<Vec<checklock::PolicyRule>>> {
+    let mut sources = vec![("command line".to_string(), specs)];
+    if let Some(path) = spec_file {
+        let text = String::from_utf8_lossy(&read(&path)?).into_owned();
+        let lines = text.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')).map(String::from).collect();
+        sources.push((path.display().to_string(), lines));
+    }
+
+    let mut rules = Vec::new();
+    for (origin, texts) in sources {
+        for text in texts {
+            match parse_yankspecs(Some(text.clone()).into_iter(), true).pop() {
+                Some(spec) => rules.push(checklock::PolicyRule { spec, text, origin: origin.clone() }),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    if let Some(profile) = profile {
+        let defaults = Defaults::load_or_bundled(defaults.as_ref().map(|p| p.as_path()))?;
+        let profile = if profile.is_empty() {
+            defaults.default_profile()
+        } else {
+            match defaults.find(&profile) {
+                Some(p) => p,
+                None => return io_err(&format!("There's no profile '{}'. See `cargo lts profiles`", profile)),
+            }
+        };
+        let yanked = defaults.yanked(profile);
+        for (y, spec) in yanked.iter().zip(profiles::to_yank_specs(&yanked)) {
+            rules.push(checklock::PolicyRule {
+                spec,
+                text: format!("{} {}", y.0, y.1),
+                origin: format!("{} profile", profile.name),
+            });
+        }
+    }
+    Ok(Some(rules))
+}
+
+fn setup_if_needed(cargo: &CargoConfig, defaults: &Defaults, profile: Option<&Profile>) -> io::Result<ForkedRegistryIndex> {
+    setup_fork(cargo, ForkedRegistryIndex::new(cargo.default_forked_index_repository_path()), defaults, profile)
+}
+
+fn setup_fork(cargo: &CargoConfig, fork: ForkedRegistryIndex, defaults: &Defaults, profile: Option<&Profile>) -> io::Result<ForkedRegistryIndex> {
+    fork.init(defaults, profile)?;
+    if fork.is_seeded_from_cache() {
+        fork.fetch_missing_crates(project_requirements(cargo)?)?;
+    }
+    if fork.is_directory() {
+        cargo.set_local_registry_source_override(&fork.registry_dir())?;
+    } else {
+        cargo.set_index_source_override(&fork.registry_dir())?;
+    }
+    Ok(fork)
+}
+
+/// Crates.io dependencies of the workspace and packages in `Cargo.lock`, as names and semver requirements
+fn project_requirements(cargo: &CargoConfig) -> io::Result<Vec<(String, String)>> {
+    let mut requirements = Vec::new();
+    for pkg in cargo.workspace_packages()? {
+        requirements.extend(pkg.dependencies.into_iter().filter(|d| d.is_from_crates_io()).map(|d| (d.name, d.req)));
+    }
+    let lockfile_path = cargo.lockfile_path();
+    if lockfile_path.exists() {
+        requirements.extend(lockfile::Lockfile::load(&lockfile_path)?.crates_io_packages().into_iter().map(|p| (p.name.clone(), format!("={}", p.version))));
+    }
+    Ok(requirements)
+}
+
+/// The profile only matters if the fork doesn't exist yet
+fn setup_with_bundled_defaults(cargo: &CargoConfig, default_yanks: bool) -> io::Result<ForkedRegistryIndex> {
+    let defaults = Defaults::bundled();
+    let profile = if default_yanks { Some(defaults.default_profile()) } else { None };
+    setup_if_needed(cargo, &defaults, profile)
+}
+
+fn print_pins(fork: &ForkedRegistryIndex) -> io::Result<()> {
+    let pins = fork.read_pins()?;
+    if pins.is_empty() {
+        println!("No crates are pinned");
+    }
+    for pin in pins {
+        println!("{} {} (yanked {} other versions)", pin.crate_name, pin.range, pin.yanked.len());
+    }
+    Ok(())
+}
+
+/// Default yanks of the fork's profile, and how many of the versions they cover are yanked in the fork
+fn print_default_yanks(cargo: &CargoConfig) -> io::Result<()> {
     let fork = ForkedRegistryIndex::new(cargo.default_forked_index_repository_path());
-    fork.init()?;
-    cargo.set_index_source_override(&fork.git_dir())?;
+    let applied = if fork.exists() { fork.read_applied_defaults()? } else { None };
+    let (defaults, profile_name) = match applied {
+        Some(regfork::AppliedDefaults { profile: None, .. }) => {
+            println!("The fork doesn't use default yanks");
+            return Ok(());
+        },
+        Some(regfork::AppliedDefaults { profile: Some(profile), defaults_path, .. }) => {
+            (Defaults::load_or_bundled(defaults_path.as_ref().map(|p| p.as_path()))?, profile)
+        },
+        None => {
+            let defaults = Defaults::bundled();
+            let name = defaults.default_profile.clone();
+            (defaults, name)
+        },
+    };
+    let profile = match defaults.find(&profile_name) {
+        Some(p) => p,
+        None => return io_err(&format!("Profile '{}' used by the fork doesn't exist", profile_name)),
+    };
+
+    println!("Default yanks of the {} profile:", profile.name);
+    let yanked = defaults.yanked(profile);
+    for (y, spec) in yanked.iter().zip(profiles::to_yank_specs(&yanked)) {
+        let state = if fork.exists() {
+            let matching: Vec<_> = fork.crate_index_entries(&spec.crate_name).unwrap_or_default()
+                .into_iter().filter(|e| spec.matches_version(&e.0.vers)).collect();
+            let yanked = matching.iter().filter(|e| e.0.yanked).count();
+            if matching.is_empty() {
+                "no such versions".to_string()
+            } else if yanked == matching.len() {
+                "applied".to_string()
+            } else {
+                format!("{} of {} versions yanked", yanked, matching.len())
+            }
+        } else {
+            "no fork".to_string()
+        };
+        println!("    {} {} ({})", y.0, y.1, state);
+    }
+    Ok(())
+}
+
+/// The yanks have been done already, so failure to check them isn't fatal
+fn warn_unsatisfiable(cargo: &CargoConfig, fork: &ForkedRegistryIndex, specs: &[YankSpec]) {
+    if let Err(e) = unsatisfiable::warn_unsatisfiable(cargo, fork, specs) {
+        eprintln!("warning: can't check if dependencies are still satisfiable: {}", e);
+    }
+}
+
+/// For read-only commands that shouldn't create a fork
+fn existing_fork(cargo: &CargoConfig) -> io::Result<ForkedRegistryIndex> {
+    let fork = ForkedRegistryIndex::new(cargo.default_forked_index_repository_path());
+    if !fork.exists() {
+        return io_err("There's no local fork of the registry. Run `cargo lts setup` first");
+    }
     Ok(fork)
 }
 
-fn io_err(s: &str) -> io::Result<()> {
+fn io_err<T>(s: &str) -> io::Result<T> {
     Err(io::Error::new(io::ErrorKind::Other, s))
 }
 
@@ -211,3 +797,29 @@ fn write(path: &Path, data: &[u8]) -> io::Result<()> {
     let mut f = fs::File::create(path)?;
     f.write_all(data)
 }
+
+/// Writes to a temporary file first, so that the file is never left half-written
+fn write_replacing(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    tmp_name.push(".lts-tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    if let Err(e) = write(&tmp_path, data) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    fs::rename(&tmp_path, path)
+}
+
+/// New empty dir that no other process is using
+fn unique_temp_dir(prefix: &str) -> io::Result<PathBuf> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    let mut attempt = 0;
+    loop {
+        let dir = env::temp_dir().join(format!("{}-{:08x}-{}", prefix, nanos, attempt));
+        match fs::create_dir(&dir) {
+            Ok(_) => return Ok(dir),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 1000 => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use cargo::is_crates_io_source;
+use read;
+use std::io;
+use std::path::Path;
+
+/// A `[[package]]` entry from `Cargo.lock`
+#[derive(Debug, Clone)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+    pub checksum: Option<String>,
+    pub dependencies: Vec<String>,
+}
+
+impl LockedPackage {
+    /// Both the git and the sparse protocol of the crates.io index
+    pub fn is_from_crates_io(&self) -> bool {
+        match self.source {
+            Some(ref s) => is_crates_io_source(s),
+            None => false,
+        }
+    }
+}
+
+/// Just enough of `Cargo.lock` to know which crates.io versions are in use.
+/// Understands both the old `[metadata]` checksums and the newer per-package ones.
+#[derive(Debug, Clone)]
+pub struct Lockfile {
+    pub packages: Vec<LockedPackage>,
+}
+
+impl Lockfile {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = read(path)?;
+        Ok(Self::parse(&String::from_utf8_lossy(&data)))
+    }
+
+    pub fn parse(toml: &str) -> Self {
+        let mut packages = Vec::new();
+        let mut metadata_checksums = Vec::new();
+        let mut current: Option<LockedPackage> = None;
+        let mut in_metadata = false;
+        let mut in_dependencies = false;
+
+        for line in toml.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if in_dependencies {
+                if line.starts_with(']') {
+                    in_dependencies = false;
+                } else if let Some(ref mut pkg) = current {
+                    pkg.dependencies.push(unquote(line.trim_right_matches(',')).to_string());
+                }
+                continue;
+            }
+            if line.starts_with('[') {
+                if let Some(pkg) = current.take() {
+                    packages.push(pkg);
+                }
+                in_metadata = line == "[metadata]";
+                if line == "[[package]]" {
+                    current = Some(LockedPackage {
+                        name: String::new(),
+                        version: String::new(),
+                        source: None,
+                        checksum: None,
+                        dependencies: Vec::new(),
+                    });
+                }
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(pos) => (line[..pos].trim(), line[pos + 1..].trim()),
+                None => continue,
+            };
+            if in_metadata {
+                // "checksum name version (source)" = "hash"
+                let key = unquote(key);
+                if key.starts_with("checksum ") {
+                    let mut parts = key["checksum ".len()..].split(' ');
+                    if let (Some(name), Some(version)) = (parts.next(), parts.next()) {
+                        metadata_checksums.push((name.to_string(), version.to_string(), unquote(value).to_string()));
+                    }
+                }
+                continue;
+            }
+            if let Some(ref mut pkg) = current {
+                match key {
+                    "name" => pkg.name = unquote(value).to_string(),
+                    "version" => pkg.version = unquote(value).to_string(),
+                    "source" => pkg.source = Some(unquote(value).to_string()),
+                    "checksum" => pkg.checksum = Some(unquote(value).to_string()),
+                    "dependencies" => {
+                        if value.starts_with('[') && value.ends_with(']') {
+                            pkg.dependencies.extend(value[1..value.len() - 1].split(',')
+                                .map(|d| unquote(d.trim()).to_string())
+                                .filter(|d| !d.is_empty()));
+                        } else {
+                            in_dependencies = true;
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        }
+        if let Some(pkg) = current.take() {
+            packages.push(pkg);
+        }
+
+        for (name, version, checksum) in metadata_checksums {
+            if let Some(pkg) = packages.iter_mut().find(|p| p.name == name && p.version == version && p.checksum.is_none()) {
+                pkg.checksum = Some(checksum);
+            }
+        }
+
+        Lockfile { packages }
+    }
+
+    pub fn crates_io_packages(&self) -> Vec<&LockedPackage> {
+        self.packages.iter().filter(|p| p.is_from_crates_io()).collect()
+    }
+
+    /// Packages that are only in `self`, and only in `other`
+    pub fn difference<'a>(&'a self, other: &'a Lockfile) -> (Vec<&'a LockedPackage>, Vec<&'a LockedPackage>) {
+        let same = |a: &LockedPackage, b: &LockedPackage| a.name == b.name && a.version == b.version && a.source == b.source;
+        let only_self = self.packages.iter().filter(|a| !other.packages.iter().any(|b| same(a, b))).collect();
+        let only_other = other.packages.iter().filter(|b| !self.packages.iter().any(|a| same(a, b))).collect();
+        (only_self, only_other)
+    }
+}
+
+fn unquote(s: &str) -> &str {
+    s.trim_matches('"')
+}
+
+#[test]
+fn parse_lockfiles() {
+    let v1 = Lockfile::parse(r#"
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "libc 0.2.40 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "libc"
+version = "0.2.40"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[metadata]
+"checksum libc 0.2.40 (registry+https://github.com/rust-lang/crates.io-index)" = "6fd41f331ac7c5b8ac259b8bf82c75c0fb2e469bbf37d2becbba9a6a2221965b"
+"#);
+    assert_eq!(2, v1.packages.len());
+    assert_eq!(vec!["libc 0.2.40 (registry+https://github.com/rust-lang/crates.io-index)"], v1.packages[0].dependencies);
+    let crates_io = v1.crates_io_packages();
+    assert_eq!(1, crates_io.len());
+    assert_eq!("libc", crates_io[0].name);
+    assert_eq!(Some("6fd41f331ac7c5b8ac259b8bf82c75c0fb2e469bbf37d2becbba9a6a2221965b"), crates_io[0].checksum.as_ref().map(|s| s.as_str()));
+
+    let v3 = Lockfile::parse(r#"version = 3
+
+[[package]]
+name = "itoa"
+version = "0.4.8"
+source = "sparse+https://index.crates.io/"
+checksum = "b71991ff56294aa922b450139ee08b3bfc70982c6b2c7562771375cf73542dd4"
+
+[[package]]
+name = "serde_json"
+version = "1.0.44"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "48c575e0cc52bdd09b47f330f646cf59afc586e9c4e3ccd6fc1f625b8ea1dad7"
+dependencies = ["itoa", "ryu"]
+"#);
+    assert_eq!(2, v3.crates_io_packages().len());
+    assert_eq!(vec!["itoa", "ryu"], v3.packages[1].dependencies);
+}
@@ -0,0 +1,110 @@
+use cargo::CargoConfig;
+use regfork::ForkedRegistryIndex;
+use semver::Version as SemVer;
+use semver::VersionReq;
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use summary::{IndexSummary, VersionSummary};
+
+/// Versions to yank, by crate name, so that only the lowest version that satisfies each requirement is left.
+/// With only those left, Cargo's resolver picks minimal versions.
+///
+/// Requirements come from the workspace's `Cargo.toml`s, and non-dev dependencies of the kept versions,
+/// including optional ones, because any feature could enable them.
+/// Versions yanked by the previous `cargo lts minimal` count as unyanked, so that lowered requirements can use them.
+pub fn minimal_versions_yanks(cargo: &CargoConfig, fork: &ForkedRegistryIndex) -> io::Result<BTreeMap<String, HashSet<String>>> {
+    let summary = fork.index_summary()?;
+    let mut requirements = Vec::new();
+    for pkg in cargo.workspace_packages()? {
+        requirements.extend(pkg.dependencies.into_iter().filter(|d| d.is_from_crates_io()).map(|d| (d.name, d.req)));
+    }
+    let yanked_before: HashSet<_> = fork.read_minimal_yanks()?.into_iter().map(|y| (y.0.to_lowercase(), y.1)).collect();
+
+    let kept = minimal_versions(&summary, requirements, |crate_name, v| {
+        !v.yanked || yanked_before.contains(&(crate_name.to_string(), v.vers.clone()))
+    });
+    let mut to_yank = BTreeMap::new();
+    let mut num_yanked = 0;
+    for (crate_name, versions) in &kept {
+        let c = &summary.crates[crate_name];
+        let yanked: HashSet<_> = c.versions.iter()
+            .filter(|v| !versions.contains(&v.vers) && (!v.yanked || yanked_before.contains(&(crate_name.clone(), v.vers.clone()))))
+            .map(|v| v.vers.clone())
+            .collect();
+        num_yanked += yanked.len();
+        to_yank.insert(c.name.clone(), yanked);
+    }
+    println!("Keeping {} versions of {} crates, yanking {} others",
+        kept.values().map(|v| v.len()).sum::<usize>(), kept.len(), num_yanked);
+    Ok(to_yank)
+}
+
+/// Lowercase crate name -> lowest available versions that satisfy requirements on it,
+/// following dependencies of the kept versions
+fn minimal_versions<F>(summary: &IndexSummary, mut requirements: Vec<(String, String)>, is_available: F) -> BTreeMap<String, HashSet<String>>
+    where F: Fn(&str, &VersionSummary) -> bool
+{
+    let mut kept: BTreeMap<String, HashSet<String>> = BTreeMap::new();
+    let mut seen = HashSet::new();
+    while let Some((crate_name, req)) = requirements.pop() {
+        let crate_name = crate_name.to_lowercase();
+        if !seen.insert((crate_name.clone(), req.clone())) {
+            continue;
+        }
+        let c = match summary.crates.get(&crate_name) {
+            Some(c) => c,
+            None => continue,
+        };
+        let parsed_req = match VersionReq::parse(&req) {
+            Ok(req) => req,
+            Err(_) => continue,
+        };
+        let lowest = c.versions.iter()
+            .filter(|v| is_available(&crate_name, v))
+            .filter_map(|v| SemVer::parse(&v.vers).ok().map(|semver| (semver, v)))
+            .filter(|v| parsed_req.matches(&v.0))
+            .min_by(|a, b| a.0.cmp(&b.0));
+        // unsatisfiable requirements are left for Cargo to report
+        let lowest = match lowest {
+            Some(v) => v.1,
+            None => continue,
+        };
+        if kept.entry(crate_name).or_insert_with(HashSet::new).insert(lowest.vers.clone()) {
+            requirements.extend(lowest.deps.iter().filter(|d| !d.dev).map(|d| (d.name.clone(), d.req.clone())));
+        }
+    }
+    kept
+}
+
+#[test]
+fn keeps_lowest_versions_of_dependencies() {
+    let summary = IndexSummary::deserialize(b"lts-index-summary 1 -
+a
+\t1.0.0\tn\t
+\t1.0.1+meta\tn\t
+\t1.2.0\tn\t\tc=^2\t!d=^1
+\t0.9.0\tn\t
+b
+\t0.1.0\ty\t
+\t0.1.1\tn\t\t?A=^1.0.1
+\t0.1.2\tn\t
+c
+\t2.0.0\tn\t
+\t2.1.0\tn\t
+d
+\t1.0.0\tn\t
+").unwrap();
+    let requirements = vec![("a".to_string(), "^1.2".to_string()), ("b".to_string(), "0.1".to_string())];
+    let kept = minimal_versions(&summary, requirements.clone(), |_, v| !v.yanked);
+    let kept: Vec<_> = kept.iter().map(|(c, v)| { let mut v: Vec<_> = v.iter().cloned().collect(); v.sort(); (c.as_str(), v) }).collect();
+    // dev dependency on d isn't followed
+    assert_eq!(vec![
+        ("a", vec!["1.0.1+meta".to_string(), "1.2.0".to_string()]),
+        ("b", vec!["0.1.1".to_string()]),
+        ("c", vec!["2.0.0".to_string()]),
+    ], kept);
+
+    // yanked by a previous run
+    let kept = minimal_versions(&summary, requirements, |c, v| !v.yanked || (c == "b" && v.vers == "0.1.0"));
+    assert!(kept["b"].contains("0.1.0") && !kept.contains_key("d"));
+}
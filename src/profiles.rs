@@ -0,0 +1,193 @@
+use io_err;
+use read;
+use regfork::YankSpec;
+use semver::VersionReq;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Version of the data file format this code understands
+const FORMAT_VERSION: u32 = 1;
+
+const BUNDLED: &str = include_str!("profiles.txt");
+
+/// A curated list of yanks for a particular compiler version
+pub struct Profile {
+    pub name: String,
+    pub description: String,
+    /// Everything yanked for a newer compiler needs to be yanked for older ones too
+    pub inherits: Option<String>,
+    /// Crate name and semver range
+    pub yanked: Vec<(String, String)>,
+}
+
+/// Set of profiles from `profiles.txt` or the user's own file in the same format
+pub struct Defaults {
+    /// `None` for the list bundled with this executable
+    pub path: Option<PathBuf>,
+    pub default_profile: String,
+    pub profiles: Vec<Profile>,
+}
+
+impl Defaults {
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED).expect("bundled profiles.txt")
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = read(path)?;
+        let mut defaults = match Self::parse(&String::from_utf8_lossy(&data)) {
+            Ok(d) => d,
+            Err(e) => return io_err(&format!("{}: {}", path.display(), e)),
+        };
+        defaults.path = Some(path.to_owned());
+        Ok(defaults)
+    }
+
+    /// Bundled defaults if there's no path
+    pub fn load_or_bundled(path: Option<&Path>) -> io::Result<Self> {
+        match path {
+            Some(path) => Self::load(path),
+            None => Ok(Self::bundled()),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut format: Option<u32> = None;
+        let mut default_profile = None;
+        let mut profiles: Vec<Profile> = Vec::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: &str| Err(format!("line {}: {}", n + 1, msg));
+
+            if line.starts_with('[') && line.ends_with(']') {
+                profiles.push(Profile {
+                    name: line[1..line.len() - 1].trim().to_string(),
+                    description: String::new(),
+                    inherits: None,
+                    yanked: Vec::new(),
+                });
+                continue;
+            }
+
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let key = parts.next().unwrap();
+            let value = parts.next().unwrap_or("").trim();
+            if value.is_empty() {
+                return err("expected `crate range` or a property");
+            }
+
+            let profile = match profiles.last_mut() {
+                Some(p) => p,
+                None => {
+                    match key {
+                        "lts-defaults" => match value.parse() {
+                            Ok(v) if v <= FORMAT_VERSION => format = Some(v),
+                            _ => return err("this file needs a newer version of cargo-lts"),
+                        },
+                        "default" => default_profile = Some(value.to_string()),
+                        _ => return err("the file must start with `lts-defaults 1`"),
+                    }
+                    continue;
+                },
+            };
+            if format.is_none() {
+                return err("the file must start with `lts-defaults 1`");
+            }
+            match key {
+                "description:" => profile.description = value.to_string(),
+                "inherits:" => profile.inherits = Some(value.to_string()),
+                crate_name => {
+                    if let Err(e) = VersionReq::parse(value) {
+                        return err(&format!("semver range '{}' for '{}' doesn't parse: {}", value, crate_name, e));
+                    }
+                    profile.yanked.push((crate_name.to_string(), value.to_string()));
+                },
+            }
+        }
+
+        if format.is_none() {
+            return Err("the file must start with `lts-defaults 1`".to_string());
+        }
+        for p in &profiles {
+            if let Some(ref parent) = p.inherits {
+                if !profiles.iter().any(|p| p.name == *parent) {
+                    return Err(format!("profile '{}' inherits unknown profile '{}'", p.name, parent));
+                }
+            }
+        }
+        let default_profile = match default_profile {
+            Some(name) => name,
+            None => match profiles.first() {
+                Some(p) => p.name.clone(),
+                None => return Err("there are no profiles".to_string()),
+            },
+        };
+        if !profiles.iter().any(|p| p.name == default_profile) {
+            return Err(format!("default profile '{}' doesn't exist", default_profile));
+        }
+
+        Ok(Defaults {
+            path: None,
+            default_profile,
+            profiles,
+        })
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    pub fn default_profile(&self) -> &Profile {
+        self.find(&self.default_profile).unwrap()
+    }
+
+    /// Profile's own yanks and yanks of profiles it inherits
+    pub fn yanked(&self, profile: &Profile) -> Vec<(String, String)> {
+        let mut yanked = Vec::new();
+        let mut next = Some(profile);
+        let mut depth = 0;
+        while let Some(p) = next {
+            yanked.extend(p.yanked.iter().cloned());
+            depth += 1;
+            next = if depth < self.profiles.len() { p.inherits.as_ref().and_then(|name| self.find(name)) } else { None };
+        }
+        yanked
+    }
+}
+
+pub fn to_yank_specs(yanked: &[(String, String)]) -> Vec<YankSpec> {
+    yanked.iter().map(|y| {
+        YankSpec {
+            crate_name: y.0.clone(),
+            range: VersionReq::parse(&y.1).unwrap(),
+            yank: true,
+        }
+    }).collect()
+}
+
+#[test]
+fn bundled_profiles() {
+    let defaults = Defaults::bundled();
+    assert_eq!("old-compilers", defaults.default_profile().name);
+    for profile in &defaults.profiles {
+        assert!(!profile.description.is_empty());
+        assert!(!to_yank_specs(&defaults.yanked(profile)).is_empty());
+    }
+    let old = defaults.find("rust-1.19").unwrap();
+    assert!(defaults.yanked(old).len() > defaults.yanked(defaults.default_profile()).len());
+}
+
+#[test]
+fn parse_errors() {
+    assert!(Defaults::parse("[a]\nfoo <1").is_err());
+    assert!(Defaults::parse("lts-defaults 2\n[a]\nfoo <1").is_err());
+    assert!(Defaults::parse("lts-defaults 1\n[a]\nfoo <<1").is_err());
+    assert!(Defaults::parse("lts-defaults 1\n[a]\ninherits: b\nfoo <1").is_err());
+    let d = Defaults::parse("lts-defaults 1\n[a]\nfoo <1\n[b]\ninherits: a\nbar =1.0.0,<2").unwrap();
+    assert_eq!("a", d.default_profile);
+    assert_eq!(2, d.yanked(d.find("b").unwrap()).len());
+}
@@ -0,0 +1,129 @@
+use io_err;
+use lockfile::Lockfile;
+use regfork::{ForkedRegistryIndex, YankSpec};
+use semver::Version as SemVer;
+use semver::VersionReq;
+use std::collections::HashSet;
+use summary::IndexSummary;
+use std::io;
+
+/// Version of a crate with a dependency that only versions in the range can satisfy
+pub struct Dependent {
+    pub crate_name: String,
+    pub version: String,
+    pub req: String,
+    pub optional: bool,
+}
+
+/// Unyanked versions that would have an unsatisfiable dependency if versions matching the spec were yanked.
+/// Dev dependencies are ignored, and so are dependencies that are already unsatisfiable.
+/// With a `Cargo.lock` only its packages are checked.
+pub fn dependents(fork: &ForkedRegistryIndex, spec: &YankSpec, lock: Option<&Lockfile>) -> io::Result<Vec<Dependent>> {
+    dependents_in_summary(&fork.index_summary()?, spec, lock)
+}
+
+fn dependents_in_summary(summary: &IndexSummary, spec: &YankSpec, lock: Option<&Lockfile>) -> io::Result<Vec<Dependent>> {
+    let target = match summary.crates.get(&spec.crate_name.to_lowercase()) {
+        Some(c) => c,
+        None => return io_err(&format!("There's no crate {} in the index", spec.crate_name)),
+    };
+    // unyanked versions, and whether they're in the range
+    let target_versions: Vec<_> = target.versions.iter()
+        .filter(|v| !v.yanked)
+        .filter_map(|v| SemVer::parse(&v.vers).ok().map(|semver| (semver, spec.matches_version(&v.vers))))
+        .collect();
+    let target_name = target.name.to_lowercase();
+    let locked: Option<HashSet<(&str, &str)>> = lock.map(|lock| {
+        lock.crates_io_packages().into_iter().map(|p| (p.name.as_str(), p.version.as_str())).collect()
+    });
+
+    let mut dependents = Vec::new();
+    for c in summary.crates.values() {
+        for ver in c.versions.iter().filter(|v| !v.yanked) {
+            if let Some(ref locked) = locked {
+                if !locked.contains(&(c.name.as_str(), ver.vers.as_str())) {
+                    continue;
+                }
+            }
+            for dep in ver.deps.iter().filter(|d| !d.dev && d.name.to_lowercase() == target_name) {
+                let req = match VersionReq::parse(&dep.req) {
+                    Ok(req) => req,
+                    Err(_) => continue,
+                };
+                let mut matching = target_versions.iter().filter(|t| req.matches(&t.0)).peekable();
+                if matching.peek().is_some() && matching.all(|t| t.1) {
+                    dependents.push(Dependent {
+                        crate_name: c.name.clone(),
+                        version: ver.vers.clone(),
+                        req: dep.req.clone(),
+                        optional: dep.optional,
+                    });
+                    break;
+                }
+            }
+        }
+    }
+    dependents.sort_by(|a, b| {
+        a.crate_name.to_lowercase().cmp(&b.crate_name.to_lowercase())
+            .then_with(|| match (SemVer::parse(&a.version), SemVer::parse(&b.version)) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                _ => a.version.cmp(&b.version),
+            })
+    });
+    Ok(dependents)
+}
+
+pub fn print_dependents(spec: &YankSpec, dependents: &[Dependent]) {
+    if dependents.is_empty() {
+        println!("Nothing depends only on {} {}", spec.crate_name, spec.range);
+        return;
+    }
+    for d in dependents {
+        println!("{} {} requires {} {}{}", d.crate_name, d.version, spec.crate_name, d.req, if d.optional { " (optional)" } else { "" });
+    }
+    let mut crates: Vec<_> = dependents.iter().map(|d| d.crate_name.as_str()).collect();
+    crates.dedup();
+    println!("{} versions of {} crates depend only on {} {}", dependents.len(), crates.len(), spec.crate_name, spec.range);
+}
+
+#[test]
+fn dependents_only_on_range() {
+    let summary = IndexSummary::deserialize(b"lts-index-summary 1 -
+a
+\t1.0.0\tn\t
+\t1.1.0\tn\t
+\t1.2.0\ty\t
+\t2.0.0\tn\t
+b
+\t0.1.0\tn\t\ta=^1.1
+\t0.2.0\tn\t\ta=^1
+\t0.3.0\tn\t\t?a==1.1.0
+\t0.4.0\tn\t\t!a==1.1.0
+\t0.5.0\ty\t\ta==1.1.0
+\t0.6.0\tn\t\ta=^3
+c
+\t1.0.0\tn\t\tA=>=1.1, <1.3
+").unwrap();
+    let spec = YankSpec { crate_name: "A".into(), range: VersionReq::parse(">=1.1, <2").unwrap(), yank: true };
+    let found = dependents_in_summary(&summary, &spec, None).unwrap();
+    let found: Vec<_> = found.iter().map(|d| (d.crate_name.as_str(), d.version.as_str(), d.optional)).collect();
+    // 0.2.0 can use 1.0.0, and 0.6.0's requirement doesn't match anything
+    assert_eq!(vec![("b", "0.1.0", false), ("b", "0.3.0", true), ("c", "1.0.0", false)], found);
+
+    let lock = Lockfile::parse(r#"
+[[package]]
+name = "b"
+version = "0.3.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "c"
+version = "0.9.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#);
+    let found = dependents_in_summary(&summary, &spec, Some(&lock)).unwrap();
+    assert_eq!(vec!["0.3.0"], found.iter().map(|d| d.version.as_str()).collect::<Vec<_>>());
+
+    let missing = YankSpec { crate_name: "nope".into(), range: VersionReq::parse("*").unwrap(), yank: true };
+    assert!(dependents_in_summary(&summary, &missing, None).is_err());
+}
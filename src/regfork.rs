@@ -1,97 +1,208 @@
 const CRATES_IO_INDEX_URL: &str = "https://github.com/rust-lang/crates.io-index";
+/// Last fetched crates.io commit, without any of the fork's changes
+const UPSTREAM_REF: &str = "refs/lts/upstream";
+/// Where Cargo keeps the fork's `HEAD` in its copy in `~/.cargo/registry/index`
+const CARGO_COPY_REF: &str = "refs/remotes/origin/HEAD";
+/// For downloading individual crates' files that Cargo hasn't cached
+const SPARSE_INDEX_URL: &str = "https://index.crates.io/";
+/// Files downloaded by one curl, which reuses the connection for them
+const DOWNLOADS_PER_CURL: usize = 100;
+/// Paths given to one git command, to stay under the OS's limit on length of command lines
+const MAX_PATHS_PER_COMMAND: usize = 500;
+/// How long to wait for another `cargo lts` to finish changing the fork
+const LOCK_TIMEOUT_SECS: u64 = 60;
+const CRATES_IO_CONFIG_JSON: &[u8] = br#"{"dl":"https://static.crates.io/crates","api":"https://crates.io"}"#;
 
 use serde_json;
 use write;
+use write_replacing;
 use read;
 use io_err;
+use unique_temp_dir;
 use cargo::CargoConfig;
+use sparse_cache;
+use summary::{CrateSummary, IndexSummary};
+use profiles::{self, Defaults, Profile};
 use semver::VersionReq;
 use semver::Version as SemVer;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io;
 use std::fs;
+use std::mem;
+use std::fs::OpenOptions;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use std::process::Command;
+use std::io::Write;
+use std::process::{Command, Stdio};
 use std::path::{Path, PathBuf};
 
+/// Present in forks that are a plain directory
+const DIRECTORY_MARKER: &str = ".lts-directory";
+
 pub struct ForkedRegistryIndex {
-    git_checkout: PathBuf,
+    fork_dir: PathBuf,
+    backend: Backend,
+    /// Number of `ForkLock`s held, because methods that lock call each other
+    lock_depth: AtomicUsize,
+}
+
+/// Keeps other `cargo lts` processes from changing the fork until dropped
+pub struct ForkLock<'a> {
+    fork: &'a ForkedRegistryIndex,
+    /// Locked by the OS, which unlocks it when it's closed, or when the process dies. `None` when locked again.
+    _file: Option<fs::File>,
+}
+
+impl<'a> Drop for ForkLock<'a> {
+    fn drop(&mut self) {
+        self.fork.lock_depth.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Backend {
+    /// Checkout of crates.io's git index, with the fork's changes committed on top
+    Git,
+    /// Cargo `local-registry` in `index/` seeded from Cargo's sparse index cache, with tarballs from Cargo's download cache.
+    /// Pristine copies of crates.io's files are kept in `.lts/upstream/`, so that the fork's edits can be redone on update.
+    Directory,
 }
 
+/// Record of default yanks that have been applied to the fork, kept in its `.git` dir
+pub struct AppliedDefaults {
+    /// `None` if the fork doesn't get default yanks
+    pub profile: Option<String>,
+    pub defaults_path: Option<PathBuf>,
+    /// Crate names and semver ranges
+    pub yanked: Vec<(String, String)>,
+    /// Crate names and versions that the yanks have been applied to, so that `update` applies them only to new versions.
+    /// `None` in records made before cargo-lts kept track of this.
+    pub applied_to: Option<Vec<(String, String)>>,
+}
+
+/// Crate pinned to versions matching a range, with every other version yanked
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pin {
+    pub crate_name: String,
+    pub range: String,
+    /// Versions yanked by the pin, which `unpin` brings back
+    pub yanked: Vec<String>,
+}
+
+/// Versions and their yanked state
+type YankedStates = Vec<(String, bool)>;
+
+/// Crate files changed in the fork since the last commit, with their previous contents
+pub struct Edits {
+    originals: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl Edits {
+    pub fn new() -> Self {
+        Edits { originals: Vec::new() }
+    }
+}
+
+#[derive(Clone)]
 pub struct YankSpec {
     pub crate_name: String,
     pub range: VersionReq,
     pub yank: bool,
 }
 
-/// These crates are too old to work with the current compiler (pre-1.0 Rust or pre-NLL bugs)
-const DEFAULT_YANKED: &'static [(&'static str, &'static str)] = &[
-    ("backtrace", "<0.2.3"),
-    ("blake2-rfc", "<0.2.17"),
-    ("cfg-if", "<0.1.9"),
-    ("conduit-mime-types", "<0.7.3"),
-    ("debug_unreachable", "<0.1.1"),
-    ("encoding", "<0.2.30"),
-    ("error", "<0.1.9"),
-    ("gcc", "<0.3.35"),
-    ("getopts", "<0.2.18"),
-    ("gif", "<0.6.0"),
-    ("hyper", "<0.1.13"),
-    ("itertools", "<0.3.25"),
-    ("lazy_static", "<0.1.16"),
-    ("libc", "^0.1"),
-    ("log", "<0.3.6"),
-    ("log", "<0.4.8,0.4"),
-    ("memchr", "<0.1.8"),
-    ("mime", "<0.1.3"),
-    ("mio", "<0.3.7"),
-    ("mio", "<0.6.6,0.6"),
-    ("native-tls", "<0.1.5"),
-    ("nix", "=0.5.0"),
-    ("num", "<0.1.39"),
-    ("num-bigint", "<0.1.44"),
-    ("num-rational", "<0.1.42"),
-    ("num_cpus", "<0.2.13,0.2"),
-    ("parking_lot_core", "<0.1.4"),
-    ("pest_derive", "<1.0.8"),
-    ("phantom", "0.*"),
-    ("pkg-config", "<0.3.9"),
-    ("plugin", "<0.2.6"),
-    ("podio", "<0.1.4"),
-    ("proc-macro-hack", "<0.5.3,0.5"),
-    ("rand", "<0.3.15"),
-    ("rand_isaac", "=0.1.0"),
-    ("route-recognizer", "<0.1.12"),
-    ("rustc-serialize", "<0.3.22"),
-    ("semver", "<0.1.20"),
-    ("solicit", "<0.4.3"),
-    ("tempdir", "<0.3.6"),
-    ("term", "<0.4.6,0.4"),
-    ("tokio-io", "<0.1.13"),
-    ("tokio-reactor", "<0.1.3"),
-    ("twox-hash", "<1.2.0"),
-    ("typeable", "<0.1.2"),
-    ("typemap", "<0.3.3"),
-    ("unsafe-any", "<0.3.0"),
-    ("url", "<0.2.38"),
-    ("url", "<1.6.1,1"),
-    ("void", "<0.0.5"),
-    ("void", "=1.0.0"),
-    ("winapi", "<0.1.23"),
-    ("winapi", "<0.2.5,0.2"),
-];
-
-
 impl ForkedRegistryIndex {
+    /// Uses git, unless the existing fork is a directory or git isn't installed
     pub fn new(local_repo_copy_dir: PathBuf) -> Self {
+        let backend = if local_repo_copy_dir.join(DIRECTORY_MARKER).exists() {
+            Backend::Directory
+        } else if local_repo_copy_dir.exists() || git_is_installed() {
+            Backend::Git
+        } else {
+            Backend::Directory
+        };
         ForkedRegistryIndex {
-            git_checkout: local_repo_copy_dir,
+            fork_dir: local_repo_copy_dir,
+            backend,
+            lock_depth: AtomicUsize::new(0),
+        }
+    }
+
+    /// Fork that doesn't need git (if it doesn't exist yet)
+    pub fn new_directory(local_repo_copy_dir: PathBuf) -> Self {
+        let mut fork = Self::new(local_repo_copy_dir);
+        if !fork.exists() {
+            fork.backend = Backend::Directory;
         }
+        fork
     }
 
-    pub fn init(&self) -> io::Result<()> {
-        if !self.git_checkout.exists() {
-            self.clone_crates_io_to_local_fork()?;
-            self.set_default_yanks()?;
+    pub fn is_directory(&self) -> bool {
+        self.backend == Backend::Directory
+    }
+
+    /// What Cargo needs to use as the registry: the git repo, or the `local-registry` dir
+    pub fn registry_dir(&self) -> PathBuf {
+        match self.backend {
+            Backend::Git => self.git_dir(),
+            Backend::Directory => self.fork_dir.clone(),
+        }
+    }
+
+    /// The lock file is next to the fork rather than in it, because the fork may not exist yet
+    fn lock_path(&self) -> PathBuf {
+        let mut name = self.fork_dir.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+        name.push(".lock");
+        self.fork_dir.with_file_name(name)
+    }
+
+    /// Waits until no other `cargo lts` is changing the fork. Locking again while locked is a no-op.
+    /// The lock file is never deleted, because another process could be waiting to lock the deleted file.
+    pub fn lock<'a>(&'a self) -> io::Result<ForkLock<'a>> {
+        let mut file = None;
+        if self.lock_depth.load(Ordering::SeqCst) == 0 {
+            let path = self.lock_path();
+            if let Some(parent) = path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            let f = OpenOptions::new().write(true).create(true).truncate(false).open(&path)?;
+            let start = Instant::now();
+            let mut waiting = false;
+            while !try_lock_file(&f)? {
+                if start.elapsed() >= Duration::from_secs(LOCK_TIMEOUT_SECS) {
+                    return io_err(&format!("The fork is busy: another `cargo lts` has been using it for over {}s", LOCK_TIMEOUT_SECS));
+                }
+                if !waiting {
+                    eprintln!("Waiting for another `cargo lts` to finish changing the fork");
+                    waiting = true;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+            file = Some(f);
+        }
+        self.lock_depth.fetch_add(1, Ordering::SeqCst);
+        Ok(ForkLock { fork: self, _file: file })
+    }
+
+    /// Without a profile the fork will have no default yanks
+    pub fn init(&self, defaults: &Defaults, profile: Option<&Profile>) -> io::Result<()> {
+        let _lock = self.lock()?;
+        if !self.fork_dir.exists() {
+            match self.backend {
+                Backend::Git => self.clone_crates_io_to_local_fork()?,
+                Backend::Directory => self.make_directory_fork()?,
+            }
+            match profile {
+                Some(profile) => self.set_default_yanks(defaults, profile)?,
+                None => self.write_applied_defaults(&AppliedDefaults {
+                    profile: None,
+                    defaults_path: None,
+                    yanked: Vec::new(),
+                    applied_to: Some(Vec::new()),
+                })?,
+            }
         } else {
             self.update_cloned_repo_fork()?;
         }
@@ -99,41 +210,101 @@ impl ForkedRegistryIndex {
     }
 
     pub fn deinit(&self) -> io::Result<()> {
-        println!("Deleting {}", self.git_checkout.display());
-        let _ = fs::remove_dir_all(&self.git_checkout);
-        if let Some(path) = CargoConfig::cargo_private_custom_git_repo_path(&self.git_checkout) {
-            println!("Deleting {}", path.display());
-            let _ = fs::remove_dir_all(path);
+        let _lock = self.lock()?;
+        // needs the fork to exist to know the path
+        let cargo_copy = if self.backend == Backend::Git { CargoConfig::cargo_private_custom_git_repo_path(&self.git_dir()) } else { None };
+        println!("Deleting {}", self.fork_dir.display());
+        let _ = fs::remove_dir_all(&self.fork_dir);
+        let _ = fs::remove_file(self.index_summary_path());
+        if let Some(path) = cargo_copy {
+            if path.exists() {
+                println!("Deleting {}", path.display());
+                let _ = fs::remove_dir_all(path);
+            }
         }
         Ok(())
     }
 
+    pub fn exists(&self) -> bool {
+        self.fork_dir.exists()
+    }
+
     pub fn git_dir(&self) -> PathBuf {
-        let git_dir_path = self.git_checkout.join(".git");
+        let git_dir_path = self.fork_dir.join(".git");
         if git_dir_path.exists() {
             git_dir_path
         } else {
-            self.git_checkout.clone()
+            self.fork_dir.clone()
+        }
+    }
+
+
+    fn git_add(&self, file_paths: &[&Path]) -> io::Result<()> {
+        if self.backend == Backend::Directory || file_paths.is_empty() {
+            return Ok(());
+        }
+        // relative paths are shorter, so more fit in one command line, but there can be too many for one
+        for chunk in file_paths.chunks(MAX_PATHS_PER_COMMAND) {
+            let res = Command::new("git")
+                .current_dir(&self.fork_dir)
+                .arg("add")
+                .arg("--")
+                .args(chunk.iter().map(|p| p.strip_prefix(&self.fork_dir).unwrap_or(p)))
+                .status()?;
+            if !res.success() {
+                return io_err("Failed to run git add");
+            }
         }
+        Ok(())
     }
 
+    /// Identifies the current state of the fork for `reset_to`. Directory forks keep a single copy of the index.
+    pub fn head_commit(&self) -> io::Result<String> {
+        if self.backend == Backend::Directory {
+            let snapshot = self.fork_dir.join(".lts").join("snapshot");
+            let _ = fs::remove_dir_all(&snapshot);
+            copy_dir(&self.fork_dir.join("index"), &snapshot)?;
+            return Ok("snapshot".to_string());
+        }
+        let out = Command::new("git")
+            .current_dir(&self.fork_dir)
+            .arg("rev-parse")
+            .arg("HEAD")
+            .output()?;
+        if !out.status.success() {
+            return io_err("Can't read current commit of the fork");
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+    }
 
-    fn git_add(&self, file_path: &Path) -> io::Result<()> {
+    /// Throws away all changes made to the fork after the given commit
+    pub fn reset_to(&self, commit: &str) -> io::Result<()> {
+        let _lock = self.lock()?;
+        if self.backend == Backend::Directory {
+            let index_dir = self.fork_dir.join("index");
+            fs::remove_dir_all(&index_dir)?;
+            return fs::rename(self.fork_dir.join(".lts").join(commit), index_dir);
+        }
         let res = Command::new("git")
-            .current_dir(&self.git_checkout)
-            .arg("add")
-            .arg("--")
-            .arg(file_path)
+            .current_dir(&self.fork_dir)
+            .arg("reset")
+            .arg("--quiet")
+            .arg("--hard")
+            .arg(commit)
             .status()?;
         if !res.success() {
-            return io_err("Failed to run git add");
+            return io_err("Failed to roll back the fork");
         }
+        self.sync_cargo_copy();
         Ok(())
     }
 
-    fn git_commit(&self) -> io::Result<()> {
+    pub fn git_commit(&self) -> io::Result<()> {
+        if self.backend == Backend::Directory {
+            return Ok(()); // edits are made in place
+        }
         let res = Command::new("git")
-            .current_dir(&self.git_checkout)
+            .current_dir(&self.fork_dir)
             .env("GIT_AUTHOR_NAME", "LTS")
             .env("GIT_COMMITTER_NAME", "LTS")
             .env("GIT_AUTHOR_EMAIL", "lts@lib.rs")
@@ -146,89 +317,617 @@ impl ForkedRegistryIndex {
         if !res.success() {
             return io_err("Failed to commit changes");
         }
+        self.sync_cargo_copy();
         Ok(())
     }
 
-    fn set_default_yanks(&self) -> io::Result<()> {
-        let yanks: Vec<_> = DEFAULT_YANKED.iter().map(|&(crate_name, range)| {
-            YankSpec {
-                crate_name: crate_name.to_string(),
-                range: VersionReq::parse(range).unwrap(),
-                yank: true,
+    /// Yanks every version the profile's yanks match, including ones that the user has unyanked
+    pub fn set_default_yanks(&self, defaults: &Defaults, profile: &Profile) -> io::Result<()> {
+        self.apply_default_yanks(defaults, profile, false)
+    }
+
+    /// Yanks versions matching the profile's yanks, and adds them to the record of applied defaults.
+    ///
+    /// With `only_new`, versions that the yanks have been applied to before are left alone, so that crates
+    /// unyanked by the user stay unyanked, and only versions published or added to the fork since then are yanked.
+    fn apply_default_yanks(&self, defaults: &Defaults, profile: &Profile, only_new: bool) -> io::Result<()> {
+        let _lock = self.lock()?;
+        let old_record = read(&self.applied_defaults_path()).ok();
+        let old = self.read_applied_defaults()?;
+        let profile_yanks = defaults.yanked(profile);
+        let specs = profiles::to_yank_specs(&profile_yanks);
+        // records made before versions were tracked are for forks that have had the yanks applied to every version
+        let tracked = old.as_ref().map_or(false, |a| a.applied_to.is_some());
+        let mut applied_to = old.as_ref().and_then(|a| a.applied_to.clone()).unwrap_or_default();
+        let seen: HashSet<_> = applied_to.iter().map(|a| (a.0.to_lowercase(), a.1.clone())).collect();
+        let mut yanked = old.map(|a| a.yanked).unwrap_or_default();
+        for y in profile_yanks {
+            if !yanked.contains(&y) {
+                yanked.push(y);
+            }
+        }
+
+        // a directory fork only has crates that Cargo has cached. Others get the yanks when they're added.
+        let mut crate_names: Vec<_> = specs.iter().map(|s| s.crate_name.to_lowercase()).filter(|name| self.crate_path(name).exists()).collect();
+        crate_names.sort();
+        crate_names.dedup();
+
+        let mut num_yanked = 0;
+        let res = self.apply_edits(|edits| {
+            let mut any_modified = false;
+            for crate_name in &crate_names {
+                any_modified |= self.rewrite_crate_file(edits, crate_name, false, |ver| {
+                    if !specs.iter().any(|s| same_crate_name(&s.crate_name, crate_name) && s.matches_version(&ver.vers)) {
+                        return None;
+                    }
+                    let version = (crate_name.clone(), ver.vers.clone());
+                    if !seen.contains(&version) {
+                        applied_to.push(version);
+                    } else if only_new {
+                        return None;
+                    }
+                    if only_new && !tracked {
+                        return None; // it was in the fork when the yanks were applied
+                    }
+                    if !ver.yanked {
+                        num_yanked += 1;
+                    }
+                    Some(true)
+                })?;
             }
-        }).collect();
-        self.set_yanked_state(&yanks, false)
+            // before committing, so that there can't be committed yanks missing from the record
+            self.write_applied_defaults(&AppliedDefaults {
+                profile: Some(profile.name.clone()),
+                defaults_path: defaults.path.clone(),
+                yanked: yanked.clone(),
+                applied_to: Some(mem::replace(&mut applied_to, Vec::new())),
+            })?;
+            Ok(any_modified)
+        });
+        if res.is_err() {
+            let _ = match old_record {
+                Some(data) => write(&self.applied_defaults_path(), &data),
+                None => fs::remove_file(self.applied_defaults_path()),
+            };
+        }
+        res?;
+        if only_new && num_yanked > 0 {
+            println!("Yanked {} new versions matching default yanks of the {} profile", num_yanked, profile.name);
+        }
+        Ok(())
+    }
+
+    /// Applies the profile's yanks to versions published since the last update, and to crates added to the fork since then
+    fn apply_new_default_yanks(&self) -> io::Result<()> {
+        let (profile_name, defaults_path) = match self.read_applied_defaults()? {
+            Some(AppliedDefaults { profile: Some(name), defaults_path, .. }) => (name, defaults_path),
+            Some(_) => return Ok(()), // opted out of default yanks
+            // forks made by older versions of cargo-lts have had the bundled defaults applied
+            None => (Defaults::bundled().default_profile, None),
+        };
+        if let Some(ref path) = defaults_path {
+            if !path.exists() {
+                eprintln!("warning: {} used for the fork's default yanks doesn't exist any more. Skipping new default yanks", path.display());
+                return Ok(());
+            }
+        }
+        let defaults = Defaults::load_or_bundled(defaults_path.as_ref().map(|p| p.as_path()))?;
+        let profile = match defaults.find(&profile_name) {
+            Some(p) => p,
+            None => {
+                eprintln!("warning: profile '{}' used by the fork doesn't exist any more", profile_name);
+                return Ok(());
+            },
+        };
+        self.apply_default_yanks(&defaults, profile, true)
     }
 
+    fn applied_defaults_path(&self) -> PathBuf {
+        match self.backend {
+            Backend::Git => self.git_dir().join("lts-applied-defaults"),
+            Backend::Directory => self.fork_dir.join(".lts").join("applied-defaults"),
+        }
+    }
 
-    pub fn set_yanked_state(&self, specs: &[YankSpec], verbose: bool) -> io::Result<()> {
-        let mut any_modified = false;
+    /// `None` for forks made before cargo-lts kept track of this
+    pub fn read_applied_defaults(&self) -> io::Result<Option<AppliedDefaults>> {
+        let path = self.applied_defaults_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = read(&path)?;
+        let mut applied = AppliedDefaults {
+            profile: None,
+            defaults_path: None,
+            yanked: Vec::new(),
+            applied_to: None,
+        };
+        for line in String::from_utf8_lossy(&data).lines() {
+            let mut parts = line.splitn(3, ' ');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some("profile"), Some(name), None) => applied.profile = Some(name.to_string()),
+                (Some("defaults"), Some(path), rest) => {
+                    let path = match rest {
+                        Some(rest) => format!("{} {}", path, rest),
+                        None => path.to_string(),
+                    };
+                    applied.defaults_path = Some(PathBuf::from(path));
+                },
+                (Some("yank"), Some(crate_name), Some(range)) => applied.yanked.push((crate_name.to_string(), range.to_string())),
+                (Some("tracks-versions"), None, None) => applied.applied_to = Some(Vec::new()),
+                (Some("version"), Some(crate_name), Some(version)) => if let Some(ref mut applied_to) = applied.applied_to {
+                    applied_to.push((crate_name.to_string(), version.to_string()));
+                },
+                _ => {},
+            }
+        }
+        Ok(Some(applied))
+    }
+
+    fn write_applied_defaults(&self, applied: &AppliedDefaults) -> io::Result<()> {
+        let mut out = String::new();
+        if let Some(ref profile) = applied.profile {
+            out.push_str(&format!("profile {}\n", profile));
+        }
+        if let Some(ref path) = applied.defaults_path {
+            let path = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+            out.push_str(&format!("defaults {}\n", path.display()));
+        }
+        for y in &applied.yanked {
+            out.push_str(&format!("yank {} {}\n", y.0, y.1));
+        }
+        if let Some(ref applied_to) = applied.applied_to {
+            out.push_str("tracks-versions\n");
+            for v in applied_to {
+                out.push_str(&format!("version {} {}\n", v.0, v.1));
+            }
+        }
+        write(&self.applied_defaults_path(), out.as_bytes())
+    }
+
+    fn pins_path(&self) -> PathBuf {
+        match self.backend {
+            Backend::Git => self.git_dir().join("lts-pins"),
+            Backend::Directory => self.fork_dir.join(".lts").join("pins"),
+        }
+    }
+
+    pub fn read_pins(&self) -> io::Result<Vec<Pin>> {
+        let path = self.pins_path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut pins: Vec<Pin> = Vec::new();
+        for line in String::from_utf8_lossy(&read(&path)?).lines() {
+            let mut parts = line.splitn(3, ' ');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some("pin"), Some(crate_name), Some(range)) => pins.push(Pin {
+                    crate_name: crate_name.to_string(),
+                    range: range.to_string(),
+                    yanked: Vec::new(),
+                }),
+                (Some("yanked"), Some(version), None) => if let Some(pin) = pins.last_mut() {
+                    pin.yanked.push(version.to_string());
+                },
+                _ => {},
+            }
+        }
+        Ok(pins)
+    }
+
+    fn write_pins(&self, pins: &[Pin]) -> io::Result<()> {
+        let mut out = String::new();
+        for pin in pins {
+            out.push_str(&format!("pin {} {}\n", pin.crate_name, pin.range));
+            for v in &pin.yanked {
+                out.push_str(&format!("yanked {}\n", v));
+            }
+        }
+        write(&self.pins_path(), out.as_bytes())
+    }
+
+    /// Yanks every version of the crate that doesn't match the spec, and remembers which versions it has yanked.
+    /// Pinning a pinned crate replaces the old pin. Fails if no unyanked version matches.
+    pub fn pin(&self, specs: &[YankSpec]) -> io::Result<()> {
+        let _lock = self.lock()?;
+        let old_pins = self.read_pins()?;
         for spec in specs {
-            let crate_file = self.crate_path(&spec.crate_name);
-            let jsons = read(&crate_file)?;
-            let mut lines_out = Vec::with_capacity(jsons.len());
-            let mut modified = false;
-            for line1 in jsons.split(|&c| c == b'\n') {
-                if line1.is_empty() {
-                    continue;
-                }
-                let tmp;
-                let mut line = line1;
-                if let Ok(mut ver) = serde_json::from_slice::<CrateVersion>(line) {
-                    if ver.yanked != spec.yank {
-                        if let Ok(semver) = SemVer::parse(&ver.vers) {
-                            if spec.range.matches(&semver) {
-                                ver.yanked = spec.yank;
-                                tmp = serde_json::to_vec(&ver).unwrap();
-                                line = &tmp;
-                                modified = true;
-                                if verbose {
-                                    println!("{} {} yanked = {}", spec.crate_name, ver.vers, spec.yank);
-                                }
-                            }
+            let old_yanked = old_pins.iter().find(|p| same_crate_name(&p.crate_name, &spec.crate_name)).map(|p| &p.yanked);
+            let any_available = self.crate_index_entries(&spec.crate_name)?.iter().any(|e| {
+                spec.matches_version(&e.0.vers) && (!e.0.yanked || old_yanked.map_or(false, |y| y.contains(&e.0.vers)))
+            });
+            if !any_available {
+                return io_err(&format!("No unyanked version of {} matches {}", spec.crate_name, spec.range));
+            }
+        }
+
+        let mut pins = old_pins.clone();
+        let res = self.apply_edits(|edits| {
+            let mut any_modified = false;
+            for spec in specs {
+                let old_pin = pins.iter().position(|p| same_crate_name(&p.crate_name, &spec.crate_name)).map(|i| pins.remove(i));
+                // versions yanked by the old pin are its to bring back, so they're not yanked for the new pin
+                let old_yanked = old_pin.map(|p| p.yanked).unwrap_or_default();
+                let mut yanked = Vec::new();
+                any_modified |= self.rewrite_crate_file(edits, &spec.crate_name, false, |ver| {
+                    let was_yanked_by_pin = old_yanked.contains(&ver.vers);
+                    if spec.matches_version(&ver.vers) {
+                        return if was_yanked_by_pin { Some(false) } else { None };
+                    }
+                    if !ver.yanked || was_yanked_by_pin {
+                        yanked.push(ver.vers.clone());
+                    }
+                    Some(true)
+                })?;
+                println!("Pinned {} {} (yanked {} other versions)", spec.crate_name, spec.range, yanked.len());
+                pins.push(Pin { crate_name: spec.crate_name.clone(), range: spec.range.to_string(), yanked });
+            }
+            // before committing, so that there can't be committed yanks missing from the pins
+            self.write_pins(&pins)?;
+            Ok(any_modified)
+        });
+        if res.is_err() {
+            let _ = self.write_pins(&old_pins);
+        }
+        res
+    }
+
+    /// Brings back versions yanked by the pins, which were all unyanked before pinning.
+    /// Versions that were yanked before pinning stay yanked.
+    pub fn unpin(&self, crate_names: &[String]) -> io::Result<()> {
+        let _lock = self.lock()?;
+        let (unpinned, pins): (Vec<_>, Vec<_>) = self.read_pins()?.into_iter()
+            .partition(|p| crate_names.iter().any(|n| same_crate_name(n, &p.crate_name)));
+        if let Some(name) = crate_names.iter().find(|n| !unpinned.iter().any(|p| same_crate_name(n, &p.crate_name))) {
+            return io_err(&format!("{} isn't pinned", name));
+        }
+        let res = self.apply_edits(|edits| {
+            let mut any_modified = false;
+            for pin in &unpinned {
+                any_modified |= self.rewrite_crate_file(edits, &pin.crate_name, false, |ver| {
+                    if pin.yanked.contains(&ver.vers) { Some(false) } else { None }
+                })?;
+                println!("Unpinned {}", pin.crate_name);
+            }
+            self.write_pins(&pins)?;
+            Ok(any_modified)
+        });
+        if res.is_err() {
+            let _ = self.write_pins(&unpinned.into_iter().chain(pins).collect::<Vec<_>>());
+        }
+        res
+    }
+
+    /// Yanks versions published since the crates were pinned
+    fn apply_pins(&self) -> io::Result<()> {
+        let mut pins = self.read_pins()?;
+        if pins.is_empty() {
+            return Ok(());
+        }
+        let old_pins = pins.clone();
+        let res = self.apply_edits(|edits| {
+            let mut any_modified = false;
+            for pin in &mut pins {
+                let range = match VersionReq::parse(&pin.range) {
+                    Ok(range) => range,
+                    Err(_) => continue,
+                };
+                let spec = YankSpec { crate_name: pin.crate_name.clone(), range, yank: true };
+                let yanked = &mut pin.yanked;
+                any_modified |= self.rewrite_crate_file(edits, &spec.crate_name, true, |ver| {
+                    if spec.matches_version(&ver.vers) {
+                        return None;
+                    }
+                    // merges from crates.io can bring back unyanked lines of versions the pin has yanked
+                    if !yanked.contains(&ver.vers) {
+                        if ver.yanked {
+                            return None;
                         }
+                        yanked.push(ver.vers.clone());
                     }
-                }
-                lines_out.extend_from_slice(line);
-                lines_out.push(b'\n');
+                    Some(true)
+                })?;
             }
-            if modified {
+            self.write_pins(&pins)?;
+            Ok(any_modified)
+        });
+        if res.is_err() {
+            let _ = self.write_pins(&old_pins);
+        }
+        res
+    }
+
+
+    fn minimal_yanks_path(&self) -> PathBuf {
+        match self.backend {
+            Backend::Git => self.git_dir().join("lts-minimal"),
+            Backend::Directory => self.fork_dir.join(".lts").join("minimal"),
+        }
+    }
+
+    /// Crate names and versions yanked by `cargo lts minimal`
+    pub fn read_minimal_yanks(&self) -> io::Result<Vec<(String, String)>> {
+        let path = self.minimal_yanks_path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut yanked = Vec::new();
+        for line in String::from_utf8_lossy(&read(&path)?).lines() {
+            let mut parts = line.splitn(3, ' ');
+            if let (Some("yanked"), Some(crate_name), Some(version)) = (parts.next(), parts.next(), parts.next()) {
+                yanked.push((crate_name.to_string(), version.to_string()));
+            }
+        }
+        Ok(yanked)
+    }
+
+    fn write_minimal_yanks(&self, yanked: &[(String, String)]) -> io::Result<()> {
+        let mut out = String::new();
+        for y in yanked {
+            out.push_str(&format!("yanked {} {}\n", y.0, y.1));
+        }
+        write(&self.minimal_yanks_path(), out.as_bytes())
+    }
+
+    /// Yanks the versions (by crate name), and brings back versions yanked by the previous `cargo lts minimal` that aren't
+    /// in the list. Versions that were yanked already aren't recorded, so that reverting brings back only the ones yanked here.
+    pub fn set_minimal_yanks(&self, to_yank: &BTreeMap<String, HashSet<String>>) -> io::Result<()> {
+        let _lock = self.lock()?;
+        let previous_list = self.read_minimal_yanks()?;
+        // lowercase crate name -> (name, versions)
+        let mut previous: HashMap<String, (String, HashSet<String>)> = HashMap::new();
+        for y in &previous_list {
+            previous.entry(y.0.to_lowercase()).or_insert_with(|| (y.0.clone(), HashSet::new())).1.insert(y.1.clone());
+        }
+        let no_versions = HashSet::new();
+        let mut crate_names: Vec<&str> = to_yank.keys().map(|n| n.as_str()).collect();
+        crate_names.extend(previous.values().map(|p| p.0.as_str()).filter(|n| !to_yank.keys().any(|k| same_crate_name(k, n))));
+
+        let res = self.apply_edits(|edits| {
+            let mut any_modified = false;
+            let mut recorded = Vec::new();
+            for crate_name in crate_names {
+                let yanking = to_yank.get(crate_name).unwrap_or(&no_versions);
+                let yanked_before = previous.get(&crate_name.to_lowercase()).map_or(&no_versions, |p| &p.1);
+                // versions to bring back may be yanked on crates.io
+                let upstream = if yanked_before.iter().any(|v| !yanking.contains(v)) {
+                    self.upstream_crate_index_entries(crate_name)?
+                } else {
+                    Vec::new()
+                };
+                any_modified |= self.rewrite_crate_file(edits, crate_name, false, |ver| {
+                    let was_yanked_before = yanked_before.contains(&ver.vers);
+                    if yanking.contains(&ver.vers) {
+                        if !ver.yanked || was_yanked_before {
+                            recorded.push((crate_name.to_string(), ver.vers.clone()));
+                        }
+                        Some(true)
+                    } else if was_yanked_before {
+                        Some(upstream.iter().find(|e| e.0.vers == ver.vers).map_or(false, |e| e.0.yanked))
+                    } else {
+                        None
+                    }
+                })?;
+            }
+            // before committing, so that there can't be committed yanks missing from the record
+            self.write_minimal_yanks(&recorded)?;
+            Ok(any_modified)
+        });
+        if res.is_err() {
+            let _ = self.write_minimal_yanks(&previous_list);
+        }
+        res
+    }
+
+    /// Brings back versions yanked by `cargo lts minimal`, unless they're yanked on crates.io
+    pub fn revert_minimal_yanks(&self) -> io::Result<()> {
+        self.set_minimal_yanks(&BTreeMap::new())
+    }
+
+    /// Returns crate names and versions whose yanked state has changed, with the new state
+    pub fn set_yanked_state(&self, specs: &[YankSpec], verbose: bool) -> io::Result<Vec<(String, String, bool)>> {
+        let _lock = self.lock()?;
+        let start = Instant::now();
+
+        // each file is rewritten once, with all specs for the crate
+        let mut crate_names = Vec::new();
+        let mut by_crate: Vec<(PathBuf, Vec<YankSpec>)> = Vec::new();
+        let mut crate_indices = HashMap::new();
+        for spec in specs {
+            let index = *crate_indices.entry(spec.crate_name.to_lowercase()).or_insert_with(|| {
+                crate_names.push(spec.crate_name.clone());
+                by_crate.push((self.crate_path(&spec.crate_name), Vec::new()));
+                by_crate.len() - 1
+            });
+            by_crate[index].1.push(spec.clone());
+        }
+        let num_crates = by_crate.len();
+
+        let mut changed = Vec::new();
+        self.apply_edits(|edits| {
+            let mut any_modified = false;
+            // files are written here rather than in the threads, so that every written file can be rolled back
+            for (crate_name, (path, rewritten)) in crate_names.iter().zip(rewrite_yanked_in_parallel(by_crate)?) {
+                let rewritten = match rewritten {
+                    Some(r) => r,
+                    None => continue,
+                };
+                for c in &rewritten.changes {
+                    if verbose {
+                        println!("{} {} yanked = {}", crate_name, c.0, c.1);
+                    }
+                    changed.push((crate_name.clone(), c.0.clone(), c.1));
+                }
+                edits.originals.push((path.clone(), rewritten.original));
+                write_replacing(&path, &rewritten.lines)?;
                 any_modified = true;
-                write(&crate_file, &lines_out)?;
-                self.git_add(&crate_file)?;
+            }
+            Ok(any_modified)
+        })?;
+        if verbose {
+            let elapsed = start.elapsed();
+            println!("Applied {} specs to {} crates in {:.2}s", specs.len(), num_crates,
+                elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9);
+        }
+        Ok(changed)
+    }
+
+    /// Runs `edit`, and commits if it returns `true`. If anything fails, files changed
+    /// by the edit are restored to the last commit, so the fork is never left half-changed.
+    pub fn apply_edits<F>(&self, edit: F) -> io::Result<()>
+        where F: FnOnce(&mut Edits) -> io::Result<bool>
+    {
+        let mut edits = Edits::new();
+        let res = match edit(&mut edits) {
+            Ok(true) => {
+                let paths: Vec<_> = edits.originals.iter().map(|o| o.0.as_path()).collect();
+                self.git_add(&paths).and_then(|_| self.git_commit())
+            },
+            Ok(false) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if res.is_err() {
+            if let Err(e) = self.roll_back(edits) {
+                eprintln!("warning: Can't restore the fork's files: {}", e);
+            }
+        }
+        res
+    }
+
+    fn roll_back(&self, edits: Edits) -> io::Result<()> {
+        if edits.originals.is_empty() {
+            return Ok(());
+        }
+        if self.backend == Backend::Git {
+            // this also unstages them
+            let mut restored = true;
+            for chunk in edits.originals.chunks(MAX_PATHS_PER_COMMAND) {
+                restored &= Command::new("git")
+                    .current_dir(&self.fork_dir)
+                    .arg("checkout")
+                    .arg("--quiet")
+                    .arg("HEAD")
+                    .arg("--")
+                    .args(chunk.iter().map(|o| o.0.strip_prefix(&self.fork_dir).unwrap_or(&o.0)))
+                    .status().map(|res| res.success()).unwrap_or(false);
+            }
+            if restored {
+                return Ok(());
             }
         }
-        if any_modified {
-            self.git_commit()?;
+        for (path, data) in edits.originals {
+            write_replacing(&path, &data)?;
         }
         Ok(())
     }
 
-    fn crate_path(&self, crate_name: &str) -> PathBuf {
-        let mut new_path = self.git_checkout.clone();
+    /// Sets yanked state of versions for which the callback returns `Some`.
+    /// Changed files are staged and committed by `apply_edits`.
+    pub fn rewrite_crate_file<F>(&self, edits: &mut Edits, crate_name: &str, verbose: bool, new_yanked_state: F) -> io::Result<bool>
+        where F: FnMut(&CrateVersion) -> Option<bool>
+    {
+        let crate_file = self.crate_path(crate_name);
+        let rewritten = match rewrite_yanked(read(&crate_file)?, new_yanked_state) {
+            Some(r) => r,
+            None => return Ok(false),
+        };
+        if verbose {
+            for c in &rewritten.changes {
+                println!("{} {} yanked = {}", crate_name, c.0, c.1);
+            }
+        }
+        if !edits.originals.iter().any(|o| o.0 == crate_file) {
+            edits.originals.push((crate_file.clone(), rewritten.original));
+        }
+        write_replacing(&crate_file, &rewritten.lines)?;
+        Ok(true)
+    }
+
+    /// Brings back versions yanked by default yanks, in a single commit.
+    /// Versions that are yanked on crates.io stay yanked.
+    pub fn revert_default_yanks(&self) -> io::Result<()> {
+        let _lock = self.lock()?;
+        let applied = match self.read_applied_defaults()? {
+            Some(a) => a.yanked,
+            None => {
+                let defaults = Defaults::bundled();
+                defaults.yanked(defaults.default_profile())
+            },
+        };
+        let specs = profiles::to_yank_specs(&applied);
+
+        // directory forks and forks made from Cargo's cache have only some crates
+        let mut crate_names: Vec<_> = specs.iter().map(|s| s.crate_name.as_str()).filter(|name| self.crate_path(name).exists()).collect();
+        crate_names.sort();
+        crate_names.dedup();
 
-        match crate_name.len() {
-            0 => {},
-            1 => new_path.push("1"),
-            2 => new_path.push("2"),
-            3 => {
-                new_path.push("3");
-                new_path.push(&crate_name[0..1]);
+        self.apply_edits(|edits| {
+            let mut any_modified = false;
+            for crate_name in crate_names {
+                let upstream = self.upstream_crate_index_entries(crate_name)?;
+                let modified = self.rewrite_crate_file(edits, crate_name, true, |ver| {
+                    if !specs.iter().any(|s| s.crate_name == crate_name && s.matches_version(&ver.vers)) {
+                        return None;
+                    }
+                    let yanked_upstream = upstream.iter().find(|e| e.0.vers == ver.vers).map_or(false, |e| e.0.yanked);
+                    Some(yanked_upstream)
+                })?;
+                any_modified |= modified;
             }
-            _ => {
-                new_path.push(&crate_name[0..2]);
-                new_path.push(&crate_name[2..4]);
+            Ok(any_modified)
+        })?;
+        self.write_applied_defaults(&AppliedDefaults {
+            profile: None,
+            defaults_path: None,
+            yanked: Vec::new(),
+            applied_to: Some(Vec::new()),
+        })
+    }
+
+    /// Every crate's file in the index
+    pub fn crate_file_paths(&self) -> io::Result<Vec<PathBuf>> {
+        let index_dir = self.index_dir();
+        let mut files = Vec::new();
+        let mut dirs = vec![index_dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                if entry.file_type()?.is_dir() {
+                    dirs.push(path);
+                } else if dir != index_dir { // config.json
+                    files.push(path);
+                }
             }
-        };
+        }
+        Ok(files)
+    }
+
+    /// Root of the index files
+    fn index_dir(&self) -> PathBuf {
+        match self.backend {
+            Backend::Git => self.fork_dir.clone(),
+            Backend::Directory => self.fork_dir.join("index"),
+        }
+    }
+
+    fn crate_path(&self, crate_name: &str) -> PathBuf {
+        self.index_dir().join(index_relative_path(crate_name))
+    }
 
-        new_path.push(crate_name);
-        new_path
+    /// All versions of the crate in the fork, with the index line each was parsed from
+    pub fn crate_index_entries(&self, crate_name: &str) -> io::Result<Vec<(CrateVersion, Vec<u8>)>> {
+        let jsons = read(&self.crate_path(crate_name))?;
+        Ok(parse_index_lines(&jsons))
     }
 
     fn fetch_crates_io_into_repo(&self) -> io::Result<()> {
         // can't reuse local on-disk index, because Cargo doesn't always update HEAD
         let res = Command::new("git")
-          .current_dir(&self.git_checkout)
+          .current_dir(&self.fork_dir)
           .env("GIT_ASKPASS", "true")
           .arg("fetch")
           .arg(CRATES_IO_INDEX_URL)
@@ -236,31 +935,394 @@ impl ForkedRegistryIndex {
         if !res.success() {
             return io_err("Update of crates.io index failed");
         }
+        self.record_upstream("FETCH_HEAD")
+    }
+
+    fn record_upstream(&self, rev: &str) -> io::Result<()> {
+        let res = Command::new("git")
+            .current_dir(&self.fork_dir)
+            .arg("update-ref")
+            .arg(UPSTREAM_REF)
+            .arg(rev)
+            .status()?;
+        if !res.success() {
+            return io_err("Failed to remember the crates.io commit");
+        }
         Ok(())
     }
 
+    /// Forks made by older versions don't have the ref, but may have FETCH_HEAD
+    fn upstream_rev(&self) -> Option<&'static str> {
+        [UPSTREAM_REF, "FETCH_HEAD"].iter().cloned().find(|rev| {
+            Command::new("git")
+                .current_dir(&self.fork_dir)
+                .arg("rev-parse")
+                .arg("--verify")
+                .arg("--quiet")
+                .arg(rev)
+                .output()
+                .map(|out| out.status.success())
+                .unwrap_or(false)
+        })
+    }
+
+    /// Versions of the crate as published on crates.io (as of the last update), ignoring changes made in the fork
+    pub fn upstream_crate_index_entries(&self, crate_name: &str) -> io::Result<Vec<(CrateVersion, Vec<u8>)>> {
+        if self.backend == Backend::Directory {
+            return match read(&self.upstream_dir().join(index_relative_path(crate_name))) {
+                Ok(jsons) => Ok(parse_index_lines(&jsons)),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+                Err(e) => Err(e),
+            };
+        }
+        let rev = match self.upstream_rev() {
+            Some(rev) => rev,
+            None => return io_err("The fork doesn't know which crates.io commit it's based on. Run `cargo lts update`"),
+        };
+        let rel_path = index_relative_path(crate_name).display().to_string().replace('\\', "/");
+        let out = Command::new("git")
+            .current_dir(&self.fork_dir)
+            .arg("show")
+            .arg(format!("{}:{}", rev, rel_path))
+            .output()?;
+        if !out.status.success() {
+            return Ok(Vec::new()); // not published upstream
+        }
+        Ok(parse_index_lines(&out.stdout))
+    }
+
+    /// Names of crates whose files in the working tree differ from the last fetched crates.io commit
+    pub fn crates_changed_since_upstream(&self) -> io::Result<Vec<String>> {
+        if self.backend == Backend::Directory {
+            let index_dir = self.index_dir();
+            let upstream_dir = self.upstream_dir();
+            let mut changed = Vec::new();
+            for path in self.crate_file_paths()? {
+                let upstream = path.strip_prefix(&index_dir).ok().and_then(|rel| read(&upstream_dir.join(rel)).ok());
+                if upstream.as_ref() != Some(&read(&path)?) {
+                    changed.extend(path.file_name().map(|n| n.to_string_lossy().into_owned()));
+                }
+            }
+            return Ok(changed);
+        }
+        let rev = match self.upstream_rev() {
+            Some(rev) => rev,
+            None => return io_err("The fork doesn't know which crates.io commit it's based on. Run `cargo lts update`"),
+        };
+        let out = Command::new("git")
+            .current_dir(&self.fork_dir)
+            .arg("diff")
+            .arg("--name-only")
+            .arg(rev)
+            .arg("--")
+            .output()?;
+        if !out.status.success() {
+            return io_err("Can't compare the fork with crates.io");
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).lines()
+            .filter(|path| path.contains('/')) // config.json
+            .filter_map(|path| path.rsplit('/').next())
+            .map(String::from)
+            .collect())
+    }
+
     pub fn update_cloned_repo_fork(&self) -> io::Result<()> {
+        let _lock = self.lock()?;
         println!("Updating index");
-        self.fetch_crates_io_into_repo()?;
+        if self.backend == Backend::Directory {
+            self.refresh_directory_fork()?;
+            self.apply_new_default_yanks()?;
+            return self.apply_pins();
+        }
+        // merges take crates.io's side of conflicting lines, which would undo yanks next to new versions
+        let edited = self.yanked_state_edits()?;
+        if self.is_seeded_from_cache() {
+            self.import_sparse_cache()?;
+            self.download_upstream_updates()?;
+            self.merge_upstream(UPSTREAM_REF)?;
+        } else {
+            // forks cloned before it was removed
+            Self::remove_origin_head(&self.fork_dir);
+            self.fetch_crates_io_into_repo()?;
+            self.merge_upstream("FETCH_HEAD")?;
+        }
+        self.redo_yanked_state_edits(&edited)?;
+        self.apply_new_default_yanks()?;
+        self.apply_pins()?;
+        // the summary is only kept if something has used it
+        if self.index_summary_path().exists() {
+            self.index_summary()?;
+        }
+        Ok(())
+    }
 
+    /// Versions whose yanked state the fork has changed, by crate, with the fork's state
+    fn yanked_state_edits(&self) -> io::Result<Vec<(String, YankedStates)>> {
+        if self.upstream_rev().is_none() {
+            return Ok(Vec::new());
+        }
+        let mut edited = Vec::new();
+        for crate_name in self.crates_changed_since_upstream()? {
+            let upstream = self.upstream_crate_index_entries(&crate_name)?;
+            let states: Vec<_> = self.crate_index_entries(&crate_name).unwrap_or_default().into_iter()
+                .filter(|f| upstream.iter().find(|u| u.0.vers == f.0.vers).map_or(false, |u| u.0.yanked != f.0.yanked))
+                .map(|f| (f.0.vers, f.0.yanked))
+                .collect();
+            if !states.is_empty() {
+                edited.push((crate_name, states));
+            }
+        }
+        Ok(edited)
+    }
+
+    /// Sets yanked state of the versions back to what `yanked_state_edits` has returned
+    fn redo_yanked_state_edits(&self, edited: &[(String, YankedStates)]) -> io::Result<()> {
+        self.apply_edits(|edits| {
+            let mut any_modified = false;
+            for e in edited {
+                any_modified |= self.rewrite_crate_file(edits, &e.0, false, |ver| e.1.iter().find(|s| s.0 == ver.vers).map(|s| s.1))?;
+            }
+            Ok(any_modified)
+        })
+    }
+
+    fn merge_upstream(&self, rev: &str) -> io::Result<()> {
         let res = Command::new("git")
-            .current_dir(&self.git_checkout)
+            .current_dir(&self.fork_dir)
+            .env("GIT_AUTHOR_NAME", "LTS")
+            .env("GIT_COMMITTER_NAME", "LTS")
+            .env("GIT_AUTHOR_EMAIL", "lts@lib.rs")
+            .env("GIT_COMMITTER_EMAIL", "lts@lib.rs")
             .arg("merge")
+            .arg("--quiet")
             .arg("-Xtheirs")
             .arg("--allow-unrelated-histories")
             .arg("-m")
             .arg("cargo lts update")
-            .arg("FETCH_HEAD")
+            .arg(rev)
             .status()?;
         if !res.success() {
             return io_err("Merge of crates.io index failed");
         }
+        self.sync_cargo_copy();
+        Ok(())
+    }
+
+    fn seeded_marker_path(&self) -> PathBuf {
+        self.git_dir().join("lts-seeded-from-cache")
+    }
 
+    /// Git fork made from Cargo's sparse index cache, rather than a clone of crates.io's git index
+    pub fn is_seeded_from_cache(&self) -> bool {
+        self.backend == Backend::Git && self.seeded_marker_path().exists()
+    }
+
+    /// Cargo's sparse index cache has every crate the user has built recently, so it's often enough
+    /// to make the fork without downloading the whole git index.
+    fn make_fork_from_sparse_cache(&self) -> io::Result<()> {
+        let parent_dir = self.fork_dir.parent().unwrap();
+        let _ = fs::create_dir_all(parent_dir);
+        let dest_tmp = parent_dir.join(".cargo-lts-making-local-fork");
+        let _ = fs::remove_dir_all(&dest_tmp);
+        fs::create_dir_all(&dest_tmp)?;
+
+        let res = Command::new("git").current_dir(&dest_tmp).arg("init").arg("--quiet").status()?;
+        if !res.success() {
+            return io_err("Failed to make a git repo for the fork");
+        }
+        let tmp_fork = ForkedRegistryIndex { fork_dir: dest_tmp.clone(), backend: Backend::Git, lock_depth: AtomicUsize::new(0) };
+        write(&tmp_fork.seeded_marker_path(), b"")?;
+        tmp_fork.import_sparse_cache()?;
+        let res = Command::new("git").current_dir(&dest_tmp).arg("reset").arg("--quiet").arg("--hard").arg(UPSTREAM_REF).status()?;
+        if !res.success() {
+            return io_err("Failed to check out the fork");
+        }
+        fs::rename(&dest_tmp, &self.fork_dir)?;
+        println!("Copied crates.io index from Cargo's cache");
+        self.sync_cargo_copy();
         Ok(())
     }
 
+    /// Commits files from Cargo's sparse index cache that have changed since the last import as a new upstream commit
+    fn import_sparse_cache(&self) -> io::Result<()> {
+        let stamp_path = self.git_dir().join("lts-cache-imported");
+        let new_stamp_path = self.git_dir().join("lts-cache-importing");
+        write(&new_stamp_path, b"")?; // before reading, so that files cached meanwhile are imported next time
+        let imported_at = fs::metadata(&stamp_path).and_then(|m| m.modified()).ok();
+
+        let mut seen = HashSet::new();
+        let mut to_import = Vec::new();
+        for cache_dir in CargoConfig::cargo_sparse_index_cache_dirs() {
+            for rel_path in sparse_cache::cached_files(&cache_dir)? {
+                if !seen.insert(rel_path.clone()) {
+                    continue;
+                }
+                let path = cache_dir.join(&rel_path);
+                if let Some(imported_at) = imported_at {
+                    if fs::metadata(&path).and_then(|m| m.modified()).map(|m| m <= imported_at).unwrap_or(false) {
+                        continue;
+                    }
+                }
+                to_import.push((rel_path.display().to_string().replace('\\', "/"), path));
+            }
+        }
+
+        let config_json = if imported_at.is_none() { Some(("config.json".to_string(), CRATES_IO_CONFIG_JSON.to_vec())) } else { None };
+        let files = config_json.into_iter().chain(to_import.into_iter().filter_map(|(rel_path, path)| {
+            read(&path).ok().and_then(|data| sparse_cache::parse_cache_file(&data)).map(|lines| (rel_path, lines))
+        }));
+        self.commit_upstream_files("crates.io index from Cargo's cache", files)?;
+        fs::rename(&new_stamp_path, &stamp_path)
+    }
+
+    /// Cargo doesn't refresh its cache of the index for projects that use the fork,
+    /// so a fork made from the cache gets new versions of its crates from crates.io's sparse index
+    fn download_upstream_updates(&self) -> io::Result<()> {
+        let crate_names: Vec<_> = self.crate_file_paths()?.iter()
+            .filter_map(|path| path.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .collect();
+        println!("Downloading index files of {} crates from crates.io", crate_names.len());
+        let mut files = Vec::new();
+        let mut failed = 0;
+        for (crate_name, data) in download_index_files(crate_names)? {
+            match data {
+                Some(data) => files.push((index_relative_path(&crate_name).display().to_string().replace('\\', "/"), data)),
+                None => failed += 1,
+            }
+        }
+        if files.is_empty() && failed > 0 {
+            return io_err("Can't download crates.io index files from index.crates.io. Check the network connection");
+        }
+        if failed > 0 {
+            eprintln!("warning: can't download crates.io index files of {} crates", failed);
+        }
+        self.commit_upstream_files("crates.io index files", files.into_iter())
+    }
+
+    /// Downloads index files of crates the project needs, and their dependencies, if they're not in the fork.
+    /// Only for forks made from Cargo's cache.
+    ///
+    /// `requirements` are crate names and semver requirements. Only dependencies of versions matching them are followed,
+    /// including optional ones, because features can turn them on.
+    pub fn fetch_missing_crates(&self, requirements: Vec<(String, String)>) -> io::Result<()> {
+        if !self.is_seeded_from_cache() {
+            return Ok(());
+        }
+        let _lock = self.lock()?;
+        // lowercase crate name -> versions, from the fork or downloaded
+        let mut crates: HashMap<String, Vec<CrateVersion>> = HashMap::new();
+        let mut unavailable = HashSet::new();
+        let mut seen = HashSet::new();
+        let mut fetched = Vec::new();
+        let mut queue = requirements;
+        while !queue.is_empty() {
+            // each round's missing crates are downloaded together
+            let mut missing: Vec<_> = queue.iter().map(|r| r.0.to_lowercase())
+                .filter(|name| !crates.contains_key(name) && !unavailable.contains(name) && !self.crate_path(name).exists())
+                .collect();
+            missing.sort();
+            missing.dedup();
+            for (crate_name, data) in download_index_files(missing)? {
+                match data {
+                    Some(data) => {
+                        crates.insert(crate_name.clone(), parse_index_lines(&data).into_iter().map(|e| e.0).collect());
+                        fetched.push((index_relative_path(&crate_name).display().to_string().replace('\\', "/"), data));
+                    },
+                    None => {
+                        eprintln!("warning: can't download crates.io index file of {}", crate_name);
+                        unavailable.insert(crate_name);
+                    },
+                }
+            }
+
+            for (crate_name, req) in mem::replace(&mut queue, Vec::new()) {
+                let crate_name = crate_name.to_lowercase();
+                if !seen.insert((crate_name.clone(), req.clone())) || unavailable.contains(&crate_name) {
+                    continue;
+                }
+                if !crates.contains_key(&crate_name) {
+                    let versions = self.crate_index_entries(&crate_name)?.into_iter().map(|e| e.0).collect();
+                    crates.insert(crate_name.clone(), versions);
+                }
+                for ver in crates[&crate_name].iter().filter(|v| !v.yanked && req_matches(&req, &v.vers)) {
+                    queue.extend(ver.dependencies().into_iter()
+                        .filter(|d| d.is_used_by_dependents())
+                        .map(|d| (d.crate_name().to_string(), d.req)));
+                }
+            }
+        }
+        if fetched.is_empty() {
+            return Ok(());
+        }
+        println!("Downloaded index files of {} crates that weren't in Cargo's cache", fetched.len());
+        self.commit_upstream_files("crates.io index files missing from Cargo's cache", fetched.into_iter())?;
+        self.merge_upstream(UPSTREAM_REF)?;
+        // default yanks have been applied before these crates were in the fork
+        self.apply_new_default_yanks()
+    }
+
+    /// Adds the files to the upstream commit (which doesn't have the fork's changes), and makes that the new upstream.
+    /// Caller needs to merge it into the fork.
+    fn commit_upstream_files<I>(&self, message: &str, files: I) -> io::Result<()> where I: Iterator<Item=(String, Vec<u8>)> {
+        let parent = self.rev_parse(UPSTREAM_REF);
+        let parent_tree = self.rev_parse(&format!("{}^{{tree}}", UPSTREAM_REF));
+        let mut child = Command::new("git")
+            .current_dir(&self.fork_dir)
+            .arg("fast-import")
+            .arg("--quiet")
+            .arg("--date-format=now")
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let written = {
+            let stdin = child.stdin.as_mut().unwrap();
+            Self::write_fast_import_commit(stdin, message, parent.is_some(), files)
+        };
+        let res = child.wait()?;
+        written?;
+        if !res.success() {
+            return io_err("Failed to import crates.io index files into the fork");
+        }
+        // don't pile up empty commits
+        if let Some(parent) = parent {
+            if parent_tree == self.rev_parse(&format!("{}^{{tree}}", UPSTREAM_REF)) {
+                self.record_upstream(&parent)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_fast_import_commit<W: Write, I>(out: &mut W, message: &str, has_parent: bool, files: I) -> io::Result<()> where I: Iterator<Item=(String, Vec<u8>)> {
+        write!(out, "commit {}\ncommitter LTS <lts@lib.rs> now\ndata {}\n{}\n", UPSTREAM_REF, message.len(), message)?;
+        if has_parent {
+            writeln!(out, "from {}^0", UPSTREAM_REF)?;
+        }
+        for (rel_path, data) in files {
+            write!(out, "M 100644 inline {}\ndata {}\n", rel_path, data.len())?;
+            out.write_all(&data)?;
+            out.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn rev_parse(&self, rev: &str) -> Option<String> {
+        let out = Command::new("git")
+            .current_dir(&self.fork_dir)
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("--quiet")
+            .arg(rev)
+            .output();
+        match out {
+            Ok(ref out) if out.status.success() => Some(String::from_utf8_lossy(&out.stdout).trim().to_string()),
+            _ => None,
+        }
+    }
+
     fn clone_crates_io_to_local_fork(&self) -> io::Result<()> {
-        let parent_dir = self.git_checkout.parent().unwrap();
+        if CargoConfig::cargo_private_crates_io_git_repo_path().is_none() && !CargoConfig::cargo_sparse_index_cache_dirs().is_empty() {
+            return self.make_fork_from_sparse_cache();
+        }
+        let parent_dir = self.fork_dir.parent().unwrap();
         let _ = fs::create_dir_all(parent_dir); // ensure parent dir exists (.cargo)
 
         // clone to a temp dir to avoid leaving broken checkout if interrupted
@@ -294,8 +1356,8 @@ impl ForkedRegistryIndex {
         // we don't want crates-io to update this, because that'd cause conflicts
         Self::remove_git_origin(&dest_tmp)?;
 
-        let _ = fs::remove_dir_all(&self.git_checkout);
-        fs::rename(&dest_tmp, &self.git_checkout)?;
+        let _ = fs::remove_dir_all(&self.fork_dir);
+        fs::rename(&dest_tmp, &self.fork_dir)?;
 
         // local crates.io copy could have been old
         // but fetch with it as a reference should be faster
@@ -303,7 +1365,7 @@ impl ForkedRegistryIndex {
         if reusing_crates_io {
             self.fetch_crates_io_into_repo()?;
             let res = Command::new("git")
-                .current_dir(&self.git_checkout)
+                .current_dir(&self.fork_dir)
                 .arg("reset")
                 .arg("--hard")
                 .arg("FETCH_HEAD")
@@ -311,20 +1373,273 @@ impl ForkedRegistryIndex {
             if !res.success() {
                 return io_err("Failed to update forked index to latest crates.io version");
             }
+        } else {
+            self.record_upstream("HEAD")?;
+        }
+
+        self.sync_cargo_copy();
+        Ok(())
+    }
+
+    /// Cargo is super slow at cloning from one dir (./fork) to another (~/.cargo/registry),
+    /// and native git can just hardlink, so keep Cargo's copy of the fork up to date for it.
+    ///
+    /// This is only an optimization, so failures are only warned about.
+    fn sync_cargo_copy(&self) {
+        if self.backend != Backend::Git {
+            return;
+        }
+        let path = match CargoConfig::cargo_private_custom_git_repo_path(&self.git_dir()) {
+            Some(path) => path,
+            None => return,
+        };
+        if let Err(err) = self.sync_cargo_copy_at(&path) {
+            eprintln!("warning: Can't update Cargo's copy of the fork in {}: {}", path.display(), err);
+        }
+    }
+
+    fn sync_cargo_copy_at(&self, path: &Path) -> io::Result<()> {
+        // Cargo keeps the repo in `.git` of that dir, without a checkout
+        let cargo_git_dir = path.join(".git");
+        if !cargo_git_dir.exists() {
+            let _ = fs::create_dir_all(path);
+            let tmp = path.join(".git-lts-tmp");
+            let _ = fs::remove_dir_all(&tmp);
+            // a local clone hardlinks objects
+            let res = Command::new("git")
+                .arg("clone")
+                .arg("--quiet")
+                .arg("--bare")
+                .arg("--local")
+                .arg(self.git_dir())
+                .arg(&tmp)
+                .status()?;
+            if !res.success() {
+                let _ = fs::remove_dir_all(&tmp);
+                return io_err("git clone failed");
+            }
+            let res = Command::new("git").arg("--git-dir").arg(&tmp).arg("config").arg("core.bare").arg("false").status()?;
+            if !res.success() {
+                let _ = fs::remove_dir_all(&tmp);
+                return io_err("git config failed");
+            }
+            fs::rename(&tmp, &cargo_git_dir)?;
+        }
+        let synced = Command::new("git")
+            .arg("--git-dir")
+            .arg(&cargo_git_dir)
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("--quiet")
+            .arg(CARGO_COPY_REF)
+            .output()?;
+        // Cargo fetches `HEAD` of the fork into this ref
+        let res = Command::new("git")
+            .arg("--git-dir")
+            .arg(&cargo_git_dir)
+            .arg("fetch")
+            .arg("--quiet")
+            .arg("--force")
+            .arg(self.git_dir())
+            .arg(format!("+HEAD:{}", CARGO_COPY_REF))
+            .status()?;
+        if !res.success() {
+            return io_err("git fetch failed");
+        }
+        let synced = String::from_utf8_lossy(&synced.stdout).trim().to_string();
+        self.invalidate_cargo_cache(&path.join(".cache"), &synced)
+    }
+
+    /// Cargo's parsed index files could outlive the commit they were made from,
+    /// so delete ones for crates that changed since the `synced` commit (all of them if that's unknown)
+    fn invalidate_cargo_cache(&self, cache_dir: &Path, synced: &str) -> io::Result<()> {
+        if !cache_dir.exists() {
+            return Ok(());
+        }
+        let changed = match self.files_changed_since(synced) {
+            Some(changed) => changed,
+            None => return fs::remove_dir_all(cache_dir),
+        };
+        for file in changed {
+            if let Err(e) = fs::remove_file(cache_dir.join(file.to_lowercase())) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Paths relative to the fork's root of files that differ between the commit and `HEAD`.
+    /// `None` if that can't be known.
+    fn files_changed_since(&self, commit: &str) -> Option<Vec<String>> {
+        if commit.is_empty() {
+            return None;
+        }
+        let out = match Command::new("git")
+            .current_dir(&self.fork_dir)
+            .arg("diff")
+            .arg("--name-only")
+            .arg("--no-renames")
+            .arg(commit)
+            .arg("HEAD")
+            .output() {
+            Ok(ref out) if out.status.success() => out.stdout.clone(),
+            _ => return None,
+        };
+        Some(String::from_utf8_lossy(&out).lines().map(|l| l.to_string()).collect())
+    }
+
+    /// The cache of `index_summary`
+    fn index_summary_path(&self) -> PathBuf {
+        let mut name = self.fork_dir.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+        name.push(".summary");
+        self.fork_dir.with_file_name(name)
+    }
+
+    /// Versions and dependencies of every crate in the fork, for queries over the whole registry.
+    ///
+    /// Git forks keep it in a file next to the fork, and update it from the files that changed since.
+    pub fn index_summary(&self) -> io::Result<IndexSummary> {
+        if self.backend == Backend::Directory {
+            // it has only crates that Cargo has cached, so reading it all is quick enough
+            return self.read_index_summary();
+        }
+        let head = self.head_commit()?;
+        let cache_path = self.index_summary_path();
+        let mut summary = match read(&cache_path).ok().and_then(|data| IndexSummary::deserialize(&data)) {
+            Some(summary) => summary,
+            None => self.read_index_summary()?,
+        };
+        if summary.commit.as_ref() == Some(&head) {
+            return Ok(summary);
+        }
+        let changed = match summary.commit {
+            Some(ref commit) => self.files_changed_since(commit),
+            None => None,
+        };
+        match changed {
+            Some(changed) => {
+                // crate files are in subdirectories, unlike config.json
+                for rel_path in changed.iter().filter(|p| p.contains('/') && !p.starts_with('.')) {
+                    match read(&self.fork_dir.join(rel_path)).ok().and_then(|data| CrateSummary::parse(&data)) {
+                        Some(c) => summary.insert(c),
+                        None => {
+                            let name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+                            summary.crates.remove(name);
+                        },
+                    }
+                }
+            },
+            None if summary.commit.is_some() => summary = self.read_index_summary()?,
+            None => {}, // has just been read
+        }
+        summary.commit = Some(head);
+        // changed files have been read from the working tree, and its uncommitted edits may yet be rolled back
+        if !self.has_uncommitted_changes() {
+            write_replacing(&cache_path, &summary.serialize())?;
+        }
+        Ok(summary)
+    }
+
+    /// Whether crate files differ from `HEAD`. Assumes they do if git can't tell.
+    fn has_uncommitted_changes(&self) -> bool {
+        Command::new("git")
+            .current_dir(&self.fork_dir)
+            .arg("diff")
+            .arg("--quiet")
+            .arg("HEAD")
+            .arg("--")
+            .status()
+            .map(|res| !res.success())
+            .unwrap_or(true)
+    }
+
+    /// Parses every crate file, without the cache
+    fn read_index_summary(&self) -> io::Result<IndexSummary> {
+        let mut summary = IndexSummary::new();
+        for c in in_parallel(self.crate_file_paths()?, |path| Ok(CrateSummary::parse(&read(&path)?)))? {
+            if let Some(c) = c {
+                summary.insert(c);
+            }
         }
+        Ok(summary)
+    }
 
-        // Cargo is super slow at cloning from one dir (./fork) to another (~/.cargo/regstry),
-        // and native git can just hardlink, so do that.
-        if let Some(path) = CargoConfig::cargo_private_custom_git_repo_path(&self.git_checkout) {
-            // the hash is wrong?
-            // if !path.exists() {
-            //     let _ = Command::new("git") // this is optional optimization
-            //         .arg("clone")
-            //         .arg("--bare")
-            //         .arg(&self.git_checkout)
-            //         .arg(path)
-            //         .status()?;
-            // }
+    fn upstream_dir(&self) -> PathBuf {
+        self.fork_dir.join(".lts").join("upstream")
+    }
+
+    fn make_directory_fork(&self) -> io::Result<()> {
+        if CargoConfig::cargo_sparse_index_cache_dirs().is_empty() {
+            return io_err("git is not installed, and Cargo hasn't cached any crates.io index files that could be used instead. \
+                Run `cargo fetch` before `cargo lts setup`");
+        }
+        let parent_dir = self.fork_dir.parent().unwrap();
+        let dest_tmp = parent_dir.join(".cargo-lts-making-local-fork");
+        let _ = fs::remove_dir_all(&dest_tmp);
+        fs::create_dir_all(dest_tmp.join("index"))?;
+        fs::create_dir_all(dest_tmp.join(".lts").join("upstream"))?;
+        write(&dest_tmp.join(DIRECTORY_MARKER), b"")?;
+        fs::rename(&dest_tmp, &self.fork_dir)?;
+        self.refresh_directory_fork()?;
+        println!("Copied crates.io index from Cargo's cache");
+        Ok(())
+    }
+
+    /// Replaces index files with newer ones from Cargo's cache, keeping yanked state of versions the fork has changed,
+    /// and links tarballs Cargo has downloaded, since a `local-registry` can't download them.
+    fn refresh_directory_fork(&self) -> io::Result<()> {
+        let index_dir = self.index_dir();
+        let upstream_dir = self.upstream_dir();
+        let mut seen = HashSet::new();
+        for cache_dir in CargoConfig::cargo_sparse_index_cache_dirs() {
+            for rel_path in sparse_cache::cached_files(&cache_dir)? {
+                if !seen.insert(rel_path.clone()) {
+                    continue;
+                }
+                let new_upstream = match sparse_cache::parse_cache_file(&read(&cache_dir.join(&rel_path))?) {
+                    Some(lines) => lines,
+                    None => continue,
+                };
+                let crate_name = match rel_path.file_name() {
+                    Some(name) => name.to_string_lossy().into_owned(),
+                    None => continue,
+                };
+                if read(&upstream_dir.join(&rel_path)).ok().as_ref() == Some(&new_upstream) {
+                    continue; // the fork's own file may have edits, but they're for the same upstream file
+                }
+                let old_upstream = self.upstream_crate_index_entries(&crate_name)?;
+                let forked = self.crate_index_entries(&crate_name).unwrap_or_default();
+                let edited: Vec<_> = forked.into_iter()
+                    .filter(|f| old_upstream.iter().find(|u| u.0.vers == f.0.vers).map_or(true, |u| u.0.yanked != f.0.yanked))
+                    .map(|f| (f.0.vers, f.0.yanked))
+                    .collect();
+
+                for dir in &[&upstream_dir, &index_dir] {
+                    let path = dir.join(&rel_path);
+                    fs::create_dir_all(path.parent().unwrap())?;
+                    write(&path, &new_upstream)?;
+                }
+                if !edited.is_empty() {
+                    // upstream files are replaced anyway, so there's nothing to roll back to
+                    self.rewrite_crate_file(&mut Edits::new(), &crate_name, false, |ver| edited.iter().find(|e| e.0 == ver.vers).map(|e| e.1))?;
+                }
+            }
+        }
+
+        for cache_dir in CargoConfig::cargo_registry_cache_dirs() {
+            for entry in fs::read_dir(&cache_dir)? {
+                let entry = entry?;
+                let file_name = entry.file_name();
+                let dest = self.fork_dir.join(&file_name);
+                if !file_name.to_string_lossy().ends_with(".crate") || dest.exists() {
+                    continue;
+                }
+                if fs::hard_link(entry.path(), &dest).is_err() {
+                    fs::copy(entry.path(), &dest)?;
+                }
+            }
         }
         Ok(())
     }
@@ -339,19 +1654,341 @@ impl ForkedRegistryIndex {
         if !res.success() {
             return io_err("failed to remove origin from git checkout");
         }
+        Self::remove_origin_head(git_repo_path);
         Ok(())
     }
+
+    /// `git remote rm` leaves `origin/HEAD` of shallow clones behind, pointing nowhere,
+    /// and then Cargo can't fetch from the fork
+    fn remove_origin_head(git_repo_path: &Path) {
+        let _ = Command::new("git")
+            .current_dir(git_repo_path)
+            .arg("symbolic-ref")
+            .arg("--delete")
+            .arg("--quiet")
+            .arg("refs/remotes/origin/HEAD")
+            .stderr(Stdio::null())
+            .status();
+    }
+}
+
+
+/// Takes an exclusive lock of the whole file, unless another process has it. `false` if it's locked already.
+#[cfg(unix)]
+fn try_lock_file(file: &fs::File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    extern "C" {
+        fn flock(fd: i32, operation: i32) -> i32;
+    }
+    const LOCK_EX: i32 = 2;
+    const LOCK_NB: i32 = 4;
+    let locked = unsafe { flock(file.as_raw_fd(), LOCK_EX | LOCK_NB) == 0 };
+    if locked {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    if err.kind() == io::ErrorKind::WouldBlock { Ok(false) } else { Err(err) }
+}
+
+#[cfg(windows)]
+fn try_lock_file(file: &fs::File) -> io::Result<bool> {
+    use std::os::windows::io::AsRawHandle;
+    #[repr(C)]
+    struct Overlapped {
+        internal: usize,
+        internal_high: usize,
+        offset: u32,
+        offset_high: u32,
+        event: *mut u8,
+    }
+    extern "system" {
+        fn LockFileEx(file: *mut u8, flags: u32, reserved: u32, bytes_low: u32, bytes_high: u32, overlapped: *mut Overlapped) -> i32;
+    }
+    const LOCKFILE_FAIL_IMMEDIATELY: u32 = 1;
+    const LOCKFILE_EXCLUSIVE_LOCK: u32 = 2;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+    let mut overlapped = Overlapped { internal: 0, internal_high: 0, offset: 0, offset_high: 0, event: ::std::ptr::null_mut() };
+    let locked = unsafe {
+        LockFileEx(file.as_raw_handle() as *mut u8, LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY, 0, !0, !0, &mut overlapped) != 0
+    };
+    if locked {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() == Some(ERROR_LOCK_VIOLATION) { Ok(false) } else { Err(err) }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn try_lock_file(_file: &fs::File) -> io::Result<bool> {
+    Ok(true)
+}
+
+fn git_is_installed() -> bool {
+    Command::new("git").arg("--version").output().map(|out| out.status.success()).unwrap_or(false)
+}
+
+fn copy_dir(src: &Path, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let dest_path = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest_path)?;
+        } else {
+            fs::copy(entry.path(), &dest_path)?;
+        }
+    }
+    Ok(())
 }
 
+/// Entry in `deps` of a `CrateVersion`
+#[derive(Deserialize, Clone, Debug)]
+pub struct Dependency {
+    pub name: String,
+    pub req: String,
+    #[serde(default)]
+    pub optional: bool,
+    pub kind: Option<String>,
+    /// Real name of a renamed dependency
+    pub package: Option<String>,
+    /// Set if it's not from crates.io
+    pub registry: Option<String>,
+}
+
+impl Dependency {
+    pub fn crate_name(&self) -> &str {
+        self.package.as_ref().unwrap_or(&self.name)
+    }
+
+    /// Normal and build dependencies on crates.io, including optional ones, because features can turn them on
+    pub fn is_used_by_dependents(&self) -> bool {
+        self.registry.is_none() && self.kind.as_ref().map_or(true, |k| k != "dev")
+    }
+}
+
+impl CrateVersion {
+    pub fn dependencies(&self) -> Vec<Dependency> {
+        self.deps.iter().filter_map(|d| serde_json::from_value(d.clone()).ok()).collect()
+    }
+}
+
+pub fn parse_index_lines(jsons: &[u8]) -> Vec<(CrateVersion, Vec<u8>)> {
+    jsons.split(|&c| c == b'\n')
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            serde_json::from_slice::<CrateVersion>(line).ok().map(|ver| (ver, line.to_vec()))
+        })
+        .collect()
+}
+
+impl YankSpec {
+    pub fn matches_version(&self, vers: &str) -> bool {
+        match SemVer::parse(vers) {
+            Ok(semver) => self.range.matches(&semver),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Up to this many threads are used to read and rewrite crate files
+const MAX_THREADS: usize = 8;
+
+/// New contents of a crate's index file
+struct Rewritten {
+    original: Vec<u8>,
+    lines: Vec<u8>,
+    /// Versions and their new yanked state
+    changes: Vec<(String, bool)>,
+}
+
+/// Sets yanked state of versions for which the callback returns `Some`. `None` if nothing changed.
+fn rewrite_yanked<F>(jsons: Vec<u8>, mut new_yanked_state: F) -> Option<Rewritten>
+    where F: FnMut(&CrateVersion) -> Option<bool>
+{
+    let mut lines_out = Vec::with_capacity(jsons.len());
+    let mut changes = Vec::new();
+    for line1 in jsons.split(|&c| c == b'\n') {
+        if line1.is_empty() {
+            continue;
+        }
+        let tmp;
+        let mut line = line1;
+        if let Ok(mut ver) = serde_json::from_slice::<CrateVersion>(line) {
+            if let Some(yank) = new_yanked_state(&ver) {
+                if ver.yanked != yank {
+                    ver.yanked = yank;
+                    tmp = serde_json::to_vec(&ver).unwrap();
+                    line = &tmp;
+                    changes.push((ver.vers, yank));
+                }
+            }
+        }
+        lines_out.extend_from_slice(line);
+        lines_out.push(b'\n');
+    }
+    if changes.is_empty() {
+        return None;
+    }
+    Some(Rewritten { original: jsons, lines: lines_out, changes })
+}
+
+/// Reads and rewrites crate files on multiple threads, without writing them.
+/// Later specs for the same version take precedence. Results are in the same order as `crates`.
+fn rewrite_yanked_in_parallel(crates: Vec<(PathBuf, Vec<YankSpec>)>) -> io::Result<Vec<(PathBuf, Option<Rewritten>)>> {
+    in_parallel(crates, |(path, specs)| {
+        let jsons = read(&path)?;
+        let rewritten = rewrite_yanked(jsons, |ver| {
+            specs.iter().rev().find(|s| s.matches_version(&ver.vers)).map(|s| s.yank)
+        });
+        Ok((path, rewritten))
+    })
+}
+
+/// Crates' files from the sparse index, with `None` for ones that can't be downloaded
+fn download_index_files(crate_names: Vec<String>) -> io::Result<Vec<(String, Option<Vec<u8>>)>> {
+    let chunks: Vec<Vec<String>> = crate_names.chunks(DOWNLOADS_PER_CURL).map(|c| c.to_vec()).collect();
+    let downloaded = in_parallel(chunks, |chunk| {
+        let dir = unique_temp_dir("cargo-lts-download")?;
+        let res = curl_into_dir(&dir, chunk);
+        let _ = fs::remove_dir_all(&dir);
+        res
+    })?;
+    let mut all = Vec::new();
+    for files in downloaded {
+        all.extend(files);
+    }
+    Ok(all)
+}
+
+fn curl_into_dir(dir: &Path, crate_names: Vec<String>) -> io::Result<Vec<(String, Option<Vec<u8>>)>> {
+    let mut cmd = Command::new("curl");
+    cmd.arg("--silent").arg("--fail").arg("--location");
+    for (i, crate_name) in crate_names.iter().enumerate() {
+        let rel_path = index_relative_path(crate_name).display().to_string().replace('\\', "/");
+        cmd.arg(format!("{}{}", SPARSE_INDEX_URL, rel_path)).arg("--output").arg(dir.join(i.to_string()));
+    }
+    // it fails if any file is missing, so the files tell which ones have been downloaded
+    if cmd.status().is_err() {
+        return io_err("curl needs to be installed to download crates.io index files that aren't in Cargo's cache");
+    }
+    Ok(crate_names.into_iter().enumerate().map(|(i, crate_name)| {
+        let data = read(&dir.join(i.to_string())).ok().and_then(|data| if data.is_empty() { None } else { Some(data) });
+        (crate_name, data)
+    }).collect())
+}
+
+/// Semver requirement from the index or `Cargo.toml`, or `=` and an exact version (which may have build metadata)
+fn req_matches(req: &str, vers: &str) -> bool {
+    if req.starts_with('=') && req[1..].trim() == vers {
+        return true;
+    }
+    match (VersionReq::parse(req), SemVer::parse(vers)) {
+        (Ok(req), Ok(vers)) => req.matches(&vers),
+        _ => false,
+    }
+}
+
+/// Maps items on up to `MAX_THREADS` threads. Results are in the same order as `items`.
+fn in_parallel<T, R, F>(items: Vec<T>, f: F) -> io::Result<Vec<R>>
+    where T: Send + 'static, R: Send + 'static, F: Fn(T) -> io::Result<R> + Send + Sync + 'static
+{
+    let chunk_size = (items.len() + MAX_THREADS - 1) / MAX_THREADS;
+    let mut chunks = Vec::new();
+    let mut items = items.into_iter().peekable();
+    while items.peek().is_some() {
+        chunks.push(items.by_ref().take(chunk_size).collect::<Vec<_>>());
+    }
+    let f = Arc::new(f);
+    let threads: Vec<_> = chunks.into_iter().map(|chunk| {
+        let f = f.clone();
+        thread::spawn(move || chunk.into_iter().map(|item| f(item)).collect::<io::Result<Vec<_>>>())
+    }).collect();
+
+    let mut all = Vec::new();
+    for t in threads {
+        match t.join() {
+            Ok(res) => all.extend(res?),
+            Err(_) => return io_err("Thread reading crate files has panicked"),
+        }
+    }
+    Ok(all)
+}
+
+/// Crate names are case-insensitive
+fn same_crate_name(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+/// Location of the crate's file relative to the root of the index
+pub fn index_relative_path(crate_name: &str) -> PathBuf {
+    let crate_name = crate_name.to_lowercase();
+    let mut new_path = PathBuf::new();
+
+    match crate_name.len() {
+        0 => {},
+        1 => new_path.push("1"),
+        2 => new_path.push("2"),
+        3 => {
+            new_path.push("3");
+            new_path.push(&crate_name[0..1]);
+        }
+        _ => {
+            new_path.push(&crate_name[0..2]);
+            new_path.push(&crate_name[2..4]);
+        }
+    };
+
+    new_path.push(crate_name);
+    new_path
+}
 
 /// A single version of a crate published to the index
 #[derive(Serialize, Deserialize, Clone, Debug)]
-struct CrateVersion {
-    name: String,
-    vers: String,
-    deps: Vec<serde_json::Value>,
-    features: Option<serde_json::Value>,
-    links: Option<String>,
-    cksum: String,
-    yanked: bool,
+pub struct CrateVersion {
+    pub name: String,
+    pub vers: String,
+    pub deps: Vec<serde_json::Value>,
+    pub features: Option<serde_json::Value>,
+    pub links: Option<String>,
+    pub cksum: String,
+    pub yanked: bool,
+    /// Minimum supported Rust version, if the crate declares it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rust_version: Option<String>,
+}
+
+#[test]
+fn keeps_rust_version() {
+    let line = br#"{"name":"a","vers":"1.0.0","deps":[],"features":{},"links":null,"cksum":"00","yanked":false,"rust_version":"1.56"}"#;
+    let ver = parse_index_lines(line).pop().unwrap().0;
+    assert_eq!(Some("1.56"), ver.rust_version.as_ref().map(|r| r.as_str()));
+    assert!(serde_json::to_string(&ver).unwrap().contains(r#""rust_version":"1.56""#));
+}
+
+#[test]
+fn rewrites_only_changed_versions() {
+    let lines = b"{\"name\":\"a\",\"vers\":\"1.0.0\",\"deps\":[],\"features\":{},\"links\":null,\"cksum\":\"00\",\"yanked\":false}\n\
+        {\"name\":\"a\",\"vers\":\"2.0.0\",\"deps\":[],\"features\":{},\"links\":null,\"cksum\":\"00\",\"yanked\":true}\n".to_vec();
+    assert!(rewrite_yanked(lines.clone(), |v| Some(v.vers == "2.0.0")).is_none());
+    let rewritten = rewrite_yanked(lines.clone(), |_| Some(true)).unwrap();
+    assert_eq!(vec![("1.0.0".to_string(), true)], rewritten.changes);
+    assert_eq!(lines, rewritten.original);
+    assert_eq!(2, parse_index_lines(&rewritten.lines).iter().filter(|v| v.0.yanked).count());
+}
+
+#[test]
+fn pins_round_trip() {
+    let dir = unique_temp_dir("cargo-lts-test-pins").unwrap();
+    let fork = ForkedRegistryIndex::new(dir.clone());
+    let range = VersionReq::parse(">= 1.0.100, < 1.0.119").unwrap();
+    let pins = vec![
+        Pin { crate_name: "serde".into(), range: range.to_string(), yanked: vec!["1.0.99".into(), "1.0.119".into()] },
+        Pin { crate_name: "Foo_bar".into(), range: "^0.1".into(), yanked: Vec::new() },
+    ];
+    fork.write_pins(&pins).unwrap();
+    let read_back = fork.read_pins();
+    let _ = fs::remove_dir_all(&dir);
+    let read_back = read_back.unwrap();
+    assert_eq!(pins, read_back);
+    assert_eq!(range.to_string(), VersionReq::parse(&read_back[0].range).unwrap().to_string());
 }
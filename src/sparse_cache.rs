@@ -0,0 +1,57 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Versions of Cargo's cache file format that are known to have the same layout
+const KNOWN_CACHE_VERSIONS: &[u8] = &[1, 2, 3];
+
+/// Index lines from a file in Cargo's sparse index cache (`~/.cargo/registry/index/index.crates.io-*/.cache`).
+///
+/// The file is a cache version byte, index format version (u32), then NUL-terminated
+/// HTTP cache header, followed by pairs of NUL-terminated version and JSON line.
+/// `None` if the file is in a format this code doesn't understand.
+pub fn parse_cache_file(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < 5 || !KNOWN_CACHE_VERSIONS.contains(&data[0]) {
+        return None;
+    }
+    let mut fields = data[5..].split(|&c| c == 0);
+    fields.next(); // etag or last-modified
+    let mut lines = Vec::with_capacity(data.len());
+    while let Some(version) = fields.next() {
+        let json = match fields.next() {
+            Some(json) => json,
+            None => break,
+        };
+        if version.is_empty() || json.is_empty() {
+            continue;
+        }
+        lines.extend_from_slice(json);
+        lines.push(b'\n');
+    }
+    Some(lines)
+}
+
+/// Every cached crate file, relative to the cache dir, which is the same layout as the index
+pub fn cached_files(cache_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![cache_dir.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else if let Ok(rel) = path.strip_prefix(cache_dir) {
+                files.push(rel.to_owned());
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[test]
+fn parse_cache() {
+    let data = b"\x03\x02\x00\x00\x00\"etag\"\x000.1.0\x00{\"vers\":\"0.1.0\"}\x000.2.0\x00{\"vers\":\"0.2.0\"}\x00";
+    assert_eq!(&b"{\"vers\":\"0.1.0\"}\n{\"vers\":\"0.2.0\"}\n"[..], &parse_cache_file(data).unwrap()[..]);
+    assert!(parse_cache_file(b"\x09\x02\x00\x00\x00x\x00").is_none());
+}
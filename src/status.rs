@@ -0,0 +1,55 @@
+use cargo::CargoConfig;
+use io_err;
+use lockfile::Lockfile;
+use regfork::ForkedRegistryIndex;
+use semver::Version as SemVer;
+use semver::VersionReq;
+use std::io;
+
+/// Lists every crates.io package in `Cargo.lock` with its yanked state in the fork and upstream,
+/// and the newest unyanked semver-compatible version available in the fork.
+pub fn print_status(cargo: &CargoConfig, fork: &ForkedRegistryIndex) -> io::Result<()> {
+    let lockfile_path = cargo.lockfile_path();
+    if !lockfile_path.exists() {
+        return io_err("There's no Cargo.lock. Run `cargo generate-lockfile` first");
+    }
+    let lock = Lockfile::load(&lockfile_path)?;
+
+    let mut yanked_in_fork = 0;
+    let mut packages = lock.crates_io_packages();
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    for pkg in packages {
+        let fork_versions: Vec<_> = fork.crate_index_entries(&pkg.name).unwrap_or_default().into_iter().map(|e| e.0).collect();
+        let fork_yanked = fork_versions.iter().find(|v| v.vers == pkg.version).map(|v| v.yanked);
+        let upstream_yanked = fork.upstream_crate_index_entries(&pkg.name)?
+            .into_iter().find(|e| e.0.vers == pkg.version).map(|e| e.0.yanked);
+
+        let state = match (fork_yanked, upstream_yanked) {
+            (None, _) => "not in the fork",
+            (Some(true), Some(true)) => "yanked upstream",
+            (Some(true), _) => "yanked in the fork",
+            (Some(false), Some(true)) => "unyanked in the fork, yanked upstream",
+            (Some(false), _) => "ok",
+        };
+        if fork_yanked == Some(true) {
+            yanked_in_fork += 1;
+        }
+
+        let newer = match (SemVer::parse(&pkg.version), VersionReq::parse(&format!("^{}", pkg.version))) {
+            (Ok(locked), Ok(req)) => fork_versions.iter()
+                .filter(|v| !v.yanked)
+                .filter_map(|v| SemVer::parse(&v.vers).ok())
+                .filter(|v| *v > locked && req.matches(v))
+                .max(),
+            _ => None,
+        };
+        match newer {
+            Some(newer) => println!("{} {}: {}, {} is available", pkg.name, pkg.version, state, newer),
+            None => println!("{} {}: {}", pkg.name, pkg.version, state),
+        }
+    }
+    if yanked_in_fork > 0 {
+        println!("{} locked packages are yanked. Run `cargo update` to replace them", yanked_in_fork);
+    }
+    Ok(())
+}
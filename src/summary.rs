@@ -0,0 +1,201 @@
+use regfork::Dependency;
+use serde_json;
+use std::collections::HashMap;
+use std::str;
+
+/// First line of the cache file, followed by the commit
+const HEADER: &str = "lts-index-summary 1";
+
+/// Dependency on a crates.io crate
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepSummary {
+    /// Real name of the crate, even if the dependency is renamed
+    pub name: String,
+    pub req: String,
+    pub optional: bool,
+    pub dev: bool,
+}
+
+impl DepSummary {
+    /// Can't be turned off, so it must be satisfiable
+    pub fn is_required(&self) -> bool {
+        !self.optional && !self.dev
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VersionSummary {
+    pub vers: String,
+    pub yanked: bool,
+    pub rust_version: Option<String>,
+    pub deps: Vec<DepSummary>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CrateSummary {
+    pub name: String,
+    pub versions: Vec<VersionSummary>,
+}
+
+/// Parts of an index line that are needed for the summary
+#[derive(Deserialize)]
+struct IndexLine {
+    name: String,
+    vers: String,
+    yanked: bool,
+    #[serde(default)]
+    rust_version: Option<String>,
+    deps: Vec<Dependency>,
+}
+
+impl CrateSummary {
+    /// From a crate's file in the index. `None` if it has no valid lines.
+    pub fn parse(jsons: &[u8]) -> Option<Self> {
+        let mut name = None;
+        let mut versions = Vec::new();
+        for line in jsons.split(|&c| c == b'\n').filter(|l| !l.is_empty()) {
+            let line: IndexLine = match serde_json::from_slice(line) {
+                Ok(line) => line,
+                Err(_) => continue,
+            };
+            versions.push(VersionSummary {
+                vers: line.vers,
+                yanked: line.yanked,
+                rust_version: line.rust_version,
+                deps: line.deps.iter().filter(|d| d.registry.is_none()).map(|d| DepSummary {
+                    name: d.crate_name().to_string(),
+                    req: d.req.clone(),
+                    optional: d.optional,
+                    dev: d.kind.as_ref().map_or(false, |k| k == "dev"),
+                }).collect(),
+            });
+            if name.is_none() {
+                name = Some(line.name);
+            }
+        }
+        name.map(|name| CrateSummary { name, versions })
+    }
+}
+
+/// Versions and dependencies of every crate in the fork
+pub struct IndexSummary {
+    /// By lowercase name
+    pub crates: HashMap<String, CrateSummary>,
+    /// Commit of the fork that the summary is up to date with
+    pub commit: Option<String>,
+}
+
+impl IndexSummary {
+    pub fn new() -> Self {
+        IndexSummary { crates: HashMap::new(), commit: None }
+    }
+
+    pub fn insert(&mut self, c: CrateSummary) {
+        self.crates.insert(c.name.to_lowercase(), c);
+    }
+
+    /// A line with the crate's name, then a tab-indented line per version: version, `y`/`n` for yanked,
+    /// `rust-version` (may be empty), and dependencies as `name=req` prefixed with `?` if optional and `!` if dev.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = String::with_capacity(self.crates.len() * 200);
+        out.push_str(&format!("{} {}\n", HEADER, self.commit.as_ref().map_or("-", |c| c.as_str())));
+        for c in self.crates.values() {
+            out.push_str(&c.name);
+            out.push('\n');
+            for v in &c.versions {
+                out.push('\t');
+                out.push_str(&v.vers);
+                out.push_str(if v.yanked { "\ty\t" } else { "\tn\t" });
+                out.push_str(v.rust_version.as_ref().map_or("", |r| r.as_str()));
+                for d in &v.deps {
+                    out.push('\t');
+                    if d.optional {
+                        out.push('?');
+                    }
+                    if d.dev {
+                        out.push('!');
+                    }
+                    out.push_str(&d.name);
+                    out.push('=');
+                    out.push_str(&d.req);
+                }
+                out.push('\n');
+            }
+        }
+        out.into_bytes()
+    }
+
+    /// `None` if it's not in the format written by `serialize`
+    pub fn deserialize(data: &[u8]) -> Option<Self> {
+        let data = match str::from_utf8(data) {
+            Ok(data) => data,
+            Err(_) => return None,
+        };
+        let mut lines = data.lines();
+        let commit = match lines.next() {
+            Some(header) if header.starts_with(HEADER) => header[HEADER.len()..].trim(),
+            _ => return None,
+        };
+        let mut summary = IndexSummary::new();
+        summary.commit = if commit == "-" || commit.is_empty() { None } else { Some(commit.to_string()) };
+        let mut current: Option<CrateSummary> = None;
+        for line in lines {
+            if !line.starts_with('\t') {
+                if let Some(c) = current.take() {
+                    summary.insert(c);
+                }
+                current = Some(CrateSummary { name: line.to_string(), versions: Vec::new() });
+                continue;
+            }
+            let mut fields = line[1..].split('\t');
+            let (vers, yanked, rust_version) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(vers), Some(yanked), Some(rust_version)) => (vers, yanked == "y", rust_version),
+                _ => return None,
+            };
+            let mut deps = Vec::new();
+            for dep in fields {
+                let optional = dep.starts_with('?');
+                let dep = dep.trim_left_matches('?');
+                let dev = dep.starts_with('!');
+                let dep = dep.trim_left_matches('!');
+                let eq = match dep.find('=') {
+                    Some(eq) => eq,
+                    None => return None,
+                };
+                deps.push(DepSummary { name: dep[..eq].to_string(), req: dep[eq + 1..].to_string(), optional, dev });
+            }
+            let version = VersionSummary {
+                vers: vers.to_string(),
+                yanked,
+                rust_version: if rust_version.is_empty() { None } else { Some(rust_version.to_string()) },
+                deps,
+            };
+            match current {
+                Some(ref mut c) => c.versions.push(version),
+                None => return None,
+            }
+        }
+        if let Some(c) = current.take() {
+            summary.insert(c);
+        }
+        Some(summary)
+    }
+}
+
+#[test]
+fn summary_round_trip() {
+    let c = CrateSummary::parse(br#"{"name":"Foo","vers":"1.0.0","deps":[{"name":"bar","req":"=1.0","features":[],"optional":true,"default_features":true,"target":null,"kind":"normal"},{"name":"b","package":"baz","req":">= 1, < 2","features":[],"optional":false,"default_features":true,"target":null,"kind":"dev"}],"features":{},"links":null,"cksum":"00","yanked":false,"rust_version":"1.56"}
+{"name":"Foo","vers":"1.1.0","deps":[],"features":{},"cksum":"00","yanked":true}
+"#).unwrap();
+    assert_eq!("Foo", c.name);
+    assert_eq!(2, c.versions.len());
+    assert_eq!(DepSummary { name: "baz".into(), req: ">= 1, < 2".into(), optional: false, dev: true }, c.versions[0].deps[1]);
+    assert!(c.versions[0].deps[0].optional && !c.versions[0].deps[0].is_required());
+
+    let mut summary = IndexSummary::new();
+    summary.commit = Some("abc".into());
+    summary.insert(c.clone());
+    let summary = IndexSummary::deserialize(&summary.serialize()).unwrap();
+    assert_eq!(Some("abc"), summary.commit.as_ref().map(|c| c.as_str()));
+    assert_eq!(Some(&c), summary.crates.get("foo"));
+}
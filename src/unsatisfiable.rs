@@ -0,0 +1,144 @@
+use cargo::CargoConfig;
+use lockfile::Lockfile;
+use regfork::{CrateVersion, ForkedRegistryIndex, YankSpec};
+use semver::Version as SemVer;
+use semver::VersionReq;
+use std::collections::HashMap;
+use std::io;
+
+/// Dependency of a package used by the project
+struct Requirement {
+    dependent: String,
+    crate_name: String,
+    req: String,
+}
+
+/// Warns about dependencies of the project (its `Cargo.toml`s and packages in `Cargo.lock`)
+/// that have no unyanked version left in the fork, and which of the `specs` caused it.
+///
+/// Returns number of unsatisfiable requirements.
+pub fn warn_unsatisfiable(cargo: &CargoConfig, fork: &ForkedRegistryIndex, specs: &[YankSpec]) -> io::Result<usize> {
+    let mut requirements = Vec::new();
+    for pkg in cargo.workspace_packages()? {
+        for dep in pkg.dependencies {
+            if dep.optional || !dep.is_from_crates_io() {
+                continue;
+            }
+            requirements.push(Requirement {
+                dependent: format!("{} {}", pkg.name, pkg.version),
+                crate_name: dep.name,
+                req: dep.req,
+            });
+        }
+    }
+
+    let lockfile_path = cargo.lockfile_path();
+    if lockfile_path.exists() {
+        let lock = Lockfile::load(&lockfile_path)?;
+        for pkg in lock.crates_io_packages() {
+            let ver = match fork.crate_index_entries(&pkg.name).unwrap_or_default().into_iter().find(|e| e.0.vers == pkg.version) {
+                Some(e) => e.0,
+                None => continue,
+            };
+            // lockfile lists deps that are actually used, including optional and platform-specific ones
+            let locked_deps: Vec<_> = pkg.dependencies.iter().filter_map(|d| d.split(' ').next()).collect();
+            for dep in ver.dependencies() {
+                if dep.registry.is_some() || dep.kind.as_ref().map_or(false, |k| k == "dev") || !locked_deps.contains(&dep.crate_name()) {
+                    continue;
+                }
+                requirements.push(Requirement {
+                    dependent: format!("{} {}", pkg.name, pkg.version),
+                    crate_name: dep.crate_name().to_string(),
+                    req: dep.req.clone(),
+                });
+            }
+        }
+    }
+
+    let warnings = unsatisfiable(&requirements, specs, |crate_name| {
+        fork.crate_index_entries(crate_name).unwrap_or_default().into_iter().map(|e| e.0).collect()
+    });
+    for w in &warnings {
+        eprintln!("warning: {}", w);
+    }
+    Ok(warnings.len())
+}
+
+/// Explains each requirement (once per crate and range) that no unyanked version satisfies,
+/// and which of the `specs` caused it. `versions_of` gets all versions of a crate.
+fn unsatisfiable<F>(requirements: &[Requirement], specs: &[YankSpec], mut versions_of: F) -> Vec<String>
+    where F: FnMut(&str) -> Vec<CrateVersion>
+{
+    let mut versions_by_crate: HashMap<String, Vec<CrateVersion>> = HashMap::new();
+    let mut reported = Vec::new();
+    let mut warnings = Vec::new();
+    for r in requirements {
+        let key = (r.crate_name.to_lowercase(), r.req.clone());
+        if reported.contains(&key) {
+            continue;
+        }
+        let req = match VersionReq::parse(&r.req) {
+            Ok(req) => req,
+            Err(_) => continue,
+        };
+        if !versions_by_crate.contains_key(&key.0) {
+            versions_by_crate.insert(key.0.clone(), versions_of(&r.crate_name));
+        }
+        let versions = &versions_by_crate[&key.0];
+
+        let matching: Vec<_> = versions.iter()
+            .filter(|v| SemVer::parse(&v.vers).map(|semver| req.matches(&semver)).unwrap_or(false))
+            .collect();
+        if matching.is_empty() || matching.iter().any(|v| !v.yanked) {
+            continue; // if nothing matches, it's not caused by yanking
+        }
+
+        let problem = if versions.iter().all(|v| v.yanked) {
+            format!("every version of {} is yanked", r.crate_name)
+        } else {
+            format!("no unyanked version of {} matches {}", r.crate_name, r.req)
+        };
+        let responsible: Vec<_> = specs.iter()
+            .filter(|s| s.yank && s.crate_name.to_lowercase() == key.0 && matching.iter().any(|v| s.matches_version(&v.vers)))
+            .map(|s| format!("{} {}", s.crate_name, s.range))
+            .collect();
+        let cause = if responsible.is_empty() {
+            "yanked earlier".to_string()
+        } else {
+            format!("yanked by {}", responsible.join(", "))
+        };
+        warnings.push(format!("{} (required by {}), {}", problem, r.dependent, cause));
+        reported.push(key);
+    }
+    warnings
+}
+
+#[test]
+fn warns_only_about_yanked_matches() {
+    use regfork::parse_index_lines;
+    let lines = br#"{"name":"a","vers":"1.0.0","deps":[],"features":{},"links":null,"cksum":"00","yanked":true}
+{"name":"a","vers":"1.1.0","deps":[],"features":{},"links":null,"cksum":"00","yanked":true}
+{"name":"a","vers":"2.0.0","deps":[],"features":{},"links":null,"cksum":"00","yanked":false}
+"#;
+    let requirement = |crate_name: &str, req: &str| Requirement {
+        dependent: "app 0.1.0".to_string(),
+        crate_name: crate_name.to_string(),
+        req: req.to_string(),
+    };
+    let requirements = vec![
+        requirement("a", "^1"),
+        requirement("A", "^1"),
+        requirement("a", "^2"),
+        requirement("a", "^3"),
+        requirement("a", "=1.0.0"),
+        requirement("missing", "^1"),
+    ];
+    let specs = vec![YankSpec { crate_name: "a".to_string(), range: VersionReq::parse("^1.1").unwrap(), yank: true }];
+    let warnings = unsatisfiable(&requirements, &specs, |crate_name| {
+        if crate_name.to_lowercase() == "a" { parse_index_lines(lines).into_iter().map(|e| e.0).collect() } else { Vec::new() }
+    });
+    assert_eq!(vec![
+        "no unyanked version of a matches ^1 (required by app 0.1.0), yanked by a ^1.1".to_string(),
+        "no unyanked version of a matches =1.0.0 (required by app 0.1.0), yanked earlier".to_string(),
+    ], warnings);
+}
@@ -0,0 +1,126 @@
+use cargo::CargoConfig;
+use io_err;
+use lockfile::Lockfile;
+use read;
+use regfork::{index_relative_path, ForkedRegistryIndex};
+use sha256;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use write;
+
+/// Makes a Cargo `local-registry` with every crates.io package from `Cargo.lock`.
+///
+/// Index lines are copied verbatim from the fork, so yanks made in the fork stay yanked.
+/// Tarballs are taken from Cargo's download cache and must match the fork's `cksum`.
+pub fn vendor(cargo: &CargoConfig, fork: &ForkedRegistryIndex, dest: &Path) -> io::Result<()> {
+    // old contents are deleted, so it must not be some other directory
+    if !is_empty_or_vendored(dest)? {
+        return io_err(&format!("{} isn't empty, and isn't from `cargo lts vendor`. Choose another directory", dest.display()));
+    }
+    cargo.cargo_generate_lockfile_if_needed()?;
+    cargo.cargo_fetch()?;
+
+    let lock = Lockfile::load(&cargo.lockfile_path())?;
+    let cache_dirs = CargoConfig::cargo_registry_cache_dirs();
+
+    let index_dir = dest.join("index");
+    let _ = fs::remove_dir_all(&index_dir); // don't keep versions that aren't locked anymore
+    fs::create_dir_all(&index_dir)?;
+    remove_crate_files(dest)?;
+
+    let packages = lock.crates_io_packages();
+    let mut index_files = BTreeMap::new();
+    for pkg in &packages {
+        let entries = fork.crate_index_entries(&pkg.name)?;
+        let (ver, line) = match entries.into_iter().find(|e| e.0.vers == pkg.version) {
+            Some(e) => e,
+            None => return io_err(&format!("{} {} is not in the forked index. Run `cargo lts update`", pkg.name, pkg.version)),
+        };
+        if let Some(ref checksum) = pkg.checksum {
+            if *checksum != ver.cksum {
+                return io_err(&format!("{} {} has checksum {} in Cargo.lock, but {} in the forked index", pkg.name, pkg.version, checksum, ver.cksum));
+            }
+        }
+
+        let tarball = find_verified_tarball(&cache_dirs, &pkg.name, &pkg.version, &ver.cksum)?;
+        let file_name = format!("{}-{}.crate", pkg.name, pkg.version);
+        fs::copy(&tarball, dest.join(&file_name))?;
+
+        let index_file = index_files.entry(index_relative_path(&pkg.name)).or_insert_with(Vec::new);
+        index_file.extend_from_slice(&line);
+        index_file.push(b'\n');
+    }
+
+    for (rel_path, lines) in &index_files {
+        let path = index_dir.join(rel_path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        write(&path, lines)?;
+    }
+
+    let config = local_registry_config_toml(&registry_path_for_config(cargo, dest));
+    write(&dest.join("cargo-config.toml"), config.as_bytes())?;
+
+    println!("Vendored {} crates into {}", packages.len(), dest.display());
+    println!("To use it, put this in .cargo/config instead of the `cargo lts` section:\n\n{}", config);
+    Ok(())
+}
+
+/// Missing, empty, or with `cargo-config.toml` written by a previous run
+fn is_empty_or_vendored(dest: &Path) -> io::Result<bool> {
+    if !dest.exists() || dest.join("cargo-config.toml").exists() {
+        return Ok(true);
+    }
+    Ok(fs::read_dir(dest)?.next().is_none())
+}
+
+/// Tarballs of crates vendored by previous runs
+fn remove_crate_files(dest: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dest)? {
+        let path = entry?.path();
+        if path.extension().map_or(false, |ext| ext == "crate") {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn find_verified_tarball(cache_dirs: &[PathBuf], name: &str, version: &str, cksum: &str) -> io::Result<PathBuf> {
+    let file_name = format!("{}-{}.crate", name, version);
+    let mut mismatched = None;
+    for dir in cache_dirs {
+        let path = dir.join(&file_name);
+        if !path.exists() {
+            continue;
+        }
+        if sha256::hex_digest(&read(&path)?) == cksum {
+            return Ok(path);
+        }
+        mismatched = Some(path);
+    }
+    match mismatched {
+        Some(path) => io_err(&format!("{} doesn't match checksum {} from the forked index", path.display(), cksum)),
+        None => io_err(&format!("{} is not in Cargo's cache. Run `cargo fetch`", file_name)),
+    }
+}
+
+/// Relative paths in `.cargo/config` are relative to the project, which keeps the vendored copy movable
+fn registry_path_for_config(cargo: &CargoConfig, dest: &Path) -> String {
+    let dest = fs::canonicalize(dest).unwrap_or_else(|_| dest.to_owned());
+    let manifest_dir = fs::canonicalize(cargo.manifest_dir()).unwrap_or_else(|_| cargo.manifest_dir().to_owned());
+    let path = match dest.strip_prefix(&manifest_dir) {
+        Ok(rel) => rel.to_owned(),
+        Err(_) => dest.clone(),
+    };
+    path.display().to_string().replace('\\', "/")
+}
+
+fn local_registry_config_toml(registry_path: &str) -> String {
+    format!("[source.crates-io]
+replace-with = 'lts-vendored'
+
+[source.lts-vendored] # `cargo lts vendor` copy of the forked registry
+local-registry = '{}'
+", registry_path)
+}
@@ -0,0 +1,78 @@
+use cargo::CargoConfig;
+use io_err;
+use lockfile::Lockfile;
+use read;
+use regfork::ForkedRegistryIndex;
+use sha256;
+use std::io;
+
+/// Hashes Cargo's downloaded tarballs of the locked packages, and compares them with `cksum` of both
+/// the fork and crates.io, so that edits of the fork can't silently change what gets built.
+///
+/// Returns number of problems found.
+pub fn verify_cache(cargo: &CargoConfig, fork: &ForkedRegistryIndex) -> io::Result<usize> {
+    let lockfile_path = cargo.lockfile_path();
+    if !lockfile_path.exists() {
+        return io_err("There's no Cargo.lock to verify. Run `cargo generate-lockfile` first");
+    }
+    let lock = Lockfile::load(&lockfile_path)?;
+    let cache_dirs = CargoConfig::cargo_registry_cache_dirs();
+
+    let mut problems = 0;
+    let mut checked = 0;
+    for pkg in lock.crates_io_packages() {
+        let fork_cksum = fork.crate_index_entries(&pkg.name).unwrap_or_default()
+            .into_iter().find(|e| e.0.vers == pkg.version).map(|e| e.0.cksum);
+        let upstream_cksum = fork.upstream_crate_index_entries(&pkg.name)?
+            .into_iter().find(|e| e.0.vers == pkg.version).map(|e| e.0.cksum);
+
+        let expected = match (fork_cksum, upstream_cksum) {
+            (Some(fork_cksum), Some(upstream_cksum)) => {
+                if fork_cksum != upstream_cksum {
+                    println!("{} {}: cksum changed in the fork from {} to {}", pkg.name, pkg.version, upstream_cksum, fork_cksum);
+                    problems += 1;
+                }
+                upstream_cksum
+            },
+            (Some(fork_cksum), None) => {
+                println!("{} {}: only in the fork, not published on crates.io", pkg.name, pkg.version);
+                problems += 1;
+                fork_cksum
+            },
+            (None, Some(upstream_cksum)) => {
+                println!("{} {}: missing from the fork", pkg.name, pkg.version);
+                problems += 1;
+                upstream_cksum
+            },
+            (None, None) => {
+                println!("{} {}: not in the crates.io index", pkg.name, pkg.version);
+                problems += 1;
+                continue;
+            },
+        };
+
+        if let Some(ref checksum) = pkg.checksum {
+            if *checksum != expected {
+                println!("{} {}: Cargo.lock has checksum {}, but crates.io has {}", pkg.name, pkg.version, checksum, expected);
+                problems += 1;
+            }
+        }
+
+        let file_name = format!("{}-{}.crate", pkg.name, pkg.version);
+        for dir in &cache_dirs {
+            let path = dir.join(&file_name);
+            if !path.exists() {
+                continue;
+            }
+            checked += 1;
+            let actual = sha256::hex_digest(&read(&path)?);
+            if actual != expected {
+                println!("{}: hash is {}, but should be {}", path.display(), actual, expected);
+                problems += 1;
+            }
+        }
+    }
+
+    println!("Verified {} cached tarballs of {} locked packages, {} problems", checked, lock.crates_io_packages().len(), problems);
+    Ok(problems)
+}
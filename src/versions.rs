@@ -0,0 +1,36 @@
+use regfork::{ForkedRegistryIndex, YankSpec};
+use std::io;
+
+/// Prints every version of the crate matching the spec, with its yanked state in the fork and on crates.io,
+/// so that yank specs can be written without digging through the index by hand.
+pub fn print_versions(fork: &ForkedRegistryIndex, spec: &YankSpec) -> io::Result<()> {
+    let versions = match fork.crate_index_entries(&spec.crate_name) {
+        Ok(v) => v,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            println!("There's no crate {} in the index", spec.crate_name);
+            return Ok(());
+        },
+        Err(e) => return Err(e),
+    };
+    let upstream = fork.upstream_crate_index_entries(&spec.crate_name)?;
+
+    println!("{:<20} {:<10} {:<10} {:<8} {:<12} deps", "version", "fork", "crates.io", "rust", "links");
+    let mut shown = 0;
+    for (ver, _) in versions.iter().filter(|e| spec.matches_version(&e.0.vers)) {
+        let yanked_state = |yanked| if yanked { "yanked" } else { "-" };
+        let upstream_state = match upstream.iter().find(|e| e.0.vers == ver.vers) {
+            Some(e) => yanked_state(e.0.yanked),
+            None => "missing",
+        };
+        println!("{:<20} {:<10} {:<10} {:<8} {:<12} {}",
+            ver.vers,
+            yanked_state(ver.yanked),
+            upstream_state,
+            ver.rust_version.as_ref().map_or("-", |r| r.as_str()),
+            ver.links.as_ref().map_or("-", |l| l.as_str()),
+            ver.deps.len());
+        shown += 1;
+    }
+    println!("{} of {} versions of {} match {}", shown, versions.len(), spec.crate_name, spec.range);
+    Ok(())
+}